@@ -0,0 +1,100 @@
+use bitboard::{Bitboard, BitboardPiece};
+
+use minimax::Team;
+
+#[cfg(test)]
+use chess_board::parse_square;
+
+pub const KNIGHT_OFFSETS: [(i32, i32); 8] = [(2, 1), (1, 2), (-1, 2), (-2, 1),
+                                             (-2, -1), (-1, -2), (1, -2), (2, -1)];
+pub const KING_OFFSETS: [(i32, i32); 8] = [(0, 1), (0, -1), (-1, 0), (1, 0),
+                                           (1, 1), (-1, -1), (-1, 1), (1, -1)];
+/* White pawns capture up the board and Black pawns down it. */
+const WHITE_PAWN_OFFSETS: [(i32, i32); 2] = [(-1, 1), (1, 1)];
+const BLACK_PAWN_OFFSETS: [(i32, i32); 2] = [(-1, -1), (1, -1)];
+
+pub const FILE_A: u64 = 0x0101010101010101;
+pub const FILE_H: u64 = 0x8080808080808080;
+pub const RANK_1: u64 = 0x00000000000000FF;
+pub const RANK_3: u64 = 0x0000000000FF0000;
+pub const RANK_6: u64 = 0x0000FF0000000000;
+pub const RANK_8: u64 = 0xFF00000000000000;
+
+/// Works out, for every square, which squares a piece that jumps by
+/// `offsets` could land on.
+const fn leaper_table(offsets: &[(i32, i32)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            let file = (square % 8) as i32 + offsets[i].0;
+            let rank = (square / 8) as i32 + offsets[i].1;
+            if file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+                table[square] |= 1 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    table
+}
+
+static KNIGHT_ATTACKS: [u64; 64] = leaper_table(&KNIGHT_OFFSETS);
+static KING_ATTACKS: [u64; 64] = leaper_table(&KING_OFFSETS);
+static WHITE_PAWN_ATTACKS: [u64; 64] = leaper_table(&WHITE_PAWN_OFFSETS);
+static BLACK_PAWN_ATTACKS: [u64; 64] = leaper_table(&BLACK_PAWN_OFFSETS);
+
+/* The one place that reaches into a Bitboard's bits, for the lookups
+ * and whole-board shifts that need them.
+ */
+pub fn bits(board: Bitboard) -> u64 {
+    board.to_u64()
+}
+
+pub fn index(square: BitboardPiece) -> usize {
+    square.rank() * 8 + square.file()
+}
+
+/// The squares a knight on `square` attacks.
+pub fn knight_attacks(square: BitboardPiece) -> Bitboard {
+    Bitboard::from_u64(KNIGHT_ATTACKS[index(square)])
+}
+
+/// The squares a king on `square` attacks.
+pub fn king_attacks(square: BitboardPiece) -> Bitboard {
+    Bitboard::from_u64(KING_ATTACKS[index(square)])
+}
+
+/// The squares a pawn of `team` on `square` attacks. Turned around,
+/// these are also the squares the other team's pawns attack `square` from.
+pub fn pawn_attacks(team: Team, square: BitboardPiece) -> Bitboard {
+    let table = match team {
+        Team::Ally => &WHITE_PAWN_ATTACKS,
+        Team::Enemy => &BLACK_PAWN_ATTACKS,
+    };
+    Bitboard::from_u64(table[index(square)])
+}
+
+#[test]
+fn test_leaper_tables() {
+    let square = |name: &str| parse_square(name).unwrap();
+    let squares = |names: &[&str]| {
+        let mut board = Bitboard::from_u64(0);
+        for name in names {
+            board.add(square(name));
+        }
+        board
+    };
+
+    assert_eq!(knight_attacks(square("a1")), squares(&["b3", "c2"]));
+    assert_eq!(knight_attacks(square("g7")).num_pieces(), 4);
+    assert_eq!(knight_attacks(square("d4")).num_pieces(), 8);
+    assert_eq!(king_attacks(square("h8")), squares(&["g8", "g7", "h7"]));
+    assert_eq!(king_attacks(square("e4")).num_pieces(), 8);
+    assert_eq!(pawn_attacks(Team::Ally, square("a2")), squares(&["b3"]));
+    assert_eq!(pawn_attacks(Team::Ally, square("e4")), squares(&["d5", "f5"]));
+    assert_eq!(pawn_attacks(Team::Enemy, square("e4")), squares(&["d3", "f3"]));
+    assert_eq!(pawn_attacks(Team::Enemy, square("h7")), squares(&["g6"]));
+    assert!(pawn_attacks(Team::Ally, square("e8")).is_empty());
+}
@@ -1,37 +1,97 @@
+use attacks::{self, KING_OFFSETS, bits};
 use color::{Color, get_color_escape_code, get_color_reset_code};
+use magic;
+use move_list::MoveList;
+use zobrist;
 
 use bitboard::{Bitboard, BitboardPiece};
 
-use minimax::board::Board;
-use minimax::Score;
+use minimax::Team;
 
+use std::error::Error;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
-macro_rules! try_move {
-    // Putting $moves:expr in here makes it more
-    // evident what's going on, however it's kinda redundant.
-    ($moves:expr, $board:expr, $piece:expr, $x:expr, $y:expr) => {
-        let file = $piece.file();
-        let rank = $piece.rank();
-        if (file as i32) + $x >= 0 && (file as i32) + $x < 8 &&
-           (rank as i32) + $y >= 0 && (rank as i32) + $y < 8 &&
-           $board.contains($piece.shift($x, $y))
-        {
-            $moves.push(ChessMove {
-                from: $piece,
-                to: $piece.shift($x, $y),
-            });
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum Piece {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl Piece {
+    pub fn from_char(c: char) -> Option<Piece> {
+        match c.to_ascii_uppercase() {
+            'P' => Some(Piece::Pawn),
+            'N' => Some(Piece::Knight),
+            'B' => Some(Piece::Bishop),
+            'R' => Some(Piece::Rook),
+            'Q' => Some(Piece::Queen),
+            'K' => Some(Piece::King),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Piece::Pawn => 'P',
+            Piece::Knight => 'N',
+            Piece::Bishop => 'B',
+            Piece::Rook => 'R',
+            Piece::Queen => 'Q',
+            Piece::King => 'K',
+        }
+    }
+
+    /// What the piece is worth in centipawns. Kings can't be traded, so
+    /// they count for nothing.
+    pub fn value(self) -> i32 {
+        match self {
+            Piece::Pawn => 100,
+            Piece::Knight => 320,
+            Piece::Bishop => 330,
+            Piece::Rook => 500,
+            Piece::Queen => 900,
+            Piece::King => 0,
         }
     }
 }
 
+/* Pieces a pawn may promote to, best first. */
+const PROMOTIONS: [Piece; 4] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight];
+
 #[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub struct ChessMove {
     from: BitboardPiece,
     to: BitboardPiece,
+    promotion: Option<Piece>,
 }
 
 impl ChessMove {
+    pub fn new(from: BitboardPiece, to: BitboardPiece, promotion: Option<Piece>) -> ChessMove {
+        ChessMove {
+            from,
+            to,
+            promotion,
+        }
+    }
+
+    pub fn from(&self) -> BitboardPiece {
+        self.from
+    }
+
+    pub fn to(&self) -> BitboardPiece {
+        self.to
+    }
+
+    pub fn promotion(&self) -> Option<Piece> {
+        self.promotion
+    }
+
+    #[cfg(test)]
     fn flip_vertical(&mut self) {
         self.from = self.from.flip_vertical();
         self.to = self.to.flip_vertical();
@@ -46,11 +106,210 @@ impl fmt::Display for ChessMove {
         write!(fmt, "{}", row_arr[self.from.file()])?;
         write!(fmt, "{}", col_arr[self.from.rank()])?;
         write!(fmt, "{}", row_arr[self.to.file()])?;
-        write!(fmt, "{}", col_arr[self.to.rank()])
+        write!(fmt, "{}", col_arr[self.to.rank()])?;
+        if let Some(piece) = self.promotion {
+            write!(fmt, "{}", piece.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the square `(x, y)` away from `square`, if it's on the board.
+fn offset(square: BitboardPiece, x: i32, y: i32) -> Option<BitboardPiece> {
+    let file = square.file() as i32 + x;
+    let rank = square.rank() as i32 + y;
+    if (0..8).contains(&file) && (0..8).contains(&rank) {
+        Some(square.shift(x, y))
+    } else {
+        None
+    }
+}
+
+/// Returns the direction to step in to walk from `from` to `to`, if
+/// they share a rank, file or diagonal.
+fn direction(from: BitboardPiece, to: BitboardPiece) -> Option<(i32, i32)> {
+    let x = to.file() as i32 - from.file() as i32;
+    let y = to.rank() as i32 - from.rank() as i32;
+    if (x == 0 && y == 0) || (x != 0 && y != 0 && x.abs() != y.abs()) {
+        None
+    } else {
+        Some((x.signum(), y.signum()))
+    }
+}
+
+/// Returns the squares strictly between `from` and `to`, or nothing if
+/// they aren't on a common line.
+fn between(from: BitboardPiece, to: BitboardPiece) -> Bitboard {
+    let mut squares = Bitboard::from_u64(0);
+    if let Some((x, y)) = direction(from, to) {
+        let mut square = from.shift(x, y);
+        while square != to {
+            squares.add(square);
+            square = square.shift(x, y);
+        }
+    }
+    squares
+}
+
+/// Returns every square on the line through `a` and `b`, or nothing
+/// if they aren't on a common line.
+fn line_through(a: BitboardPiece, b: BitboardPiece) -> Bitboard {
+    let mut squares = Bitboard::from_u64(0);
+    if let Some((x, y)) = direction(a, b) {
+        squares.add(a);
+        for &(x, y) in [(x, y), (-x, -y)].iter() {
+            let mut square = a;
+            while let Some(next) = offset(square, x, y) {
+                squares.add(next);
+                square = next;
+            }
+        }
     }
+    squares
 }
 
 #[derive(Clone,Copy,PartialEq,Eq,Hash,Debug)]
+pub struct CastlingRights {
+    kingside: bool,
+    queenside: bool,
+}
+
+impl CastlingRights {
+    fn all() -> CastlingRights {
+        CastlingRights {
+            kingside: true,
+            queenside: true,
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum DrawReason {
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+}
+
+impl fmt::Display for DrawReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DrawReason::FiftyMoveRule => write!(fmt, "the fifty-move rule"),
+            DrawReason::ThreefoldRepetition => write!(fmt, "threefold repetition"),
+            DrawReason::InsufficientMaterial => write!(fmt, "insufficient material"),
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+pub enum GameOutcome {
+    Ongoing,
+    /// The given team has checkmated the other.
+    Checkmate(Team),
+    Stalemate,
+    Draw(DrawReason),
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum FenError {
+    /// A FEN needs 4 or 6 space separated fields.
+    FieldCount(usize),
+    /// Piece placement needs exactly 8 ranks.
+    RankCount(usize),
+    /// The given rank (1-8) doesn't describe exactly 8 squares.
+    RankLength(usize),
+    UnknownPiece(char),
+    /// A pawn was placed on the first or last rank.
+    PawnOnBackRank,
+    /// Each side needs exactly one king.
+    KingCount,
+    /// A side has more than 16 pieces, or more pawns and promoted pieces
+    /// between them than the 8 pawns it started with.
+    PieceCount,
+    SideToMove(String),
+    Castling(String),
+    EnPassant(String),
+    HalfmoveClock(String),
+    FullmoveNumber(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FenError::FieldCount(n) => write!(fmt, "expected 4 or 6 fields, found {}", n),
+            FenError::RankCount(n) => write!(fmt, "expected 8 ranks, found {}", n),
+            FenError::RankLength(rank) => {
+                write!(fmt, "rank {} doesn't describe exactly 8 squares", rank)
+            }
+            FenError::UnknownPiece(c) => write!(fmt, "unknown piece '{}'", c),
+            FenError::PawnOnBackRank => write!(fmt, "pawns can't stand on the first or last rank"),
+            FenError::KingCount => write!(fmt, "each side needs exactly one king"),
+            FenError::PieceCount => write!(fmt, "a side has more pieces than it could ever have"),
+            FenError::SideToMove(ref s) => write!(fmt, "side to move must be 'w' or 'b', not '{}'", s),
+            FenError::Castling(ref s) => write!(fmt, "bad castling rights '{}'", s),
+            FenError::EnPassant(ref s) => write!(fmt, "bad en passant square '{}'", s),
+            FenError::HalfmoveClock(ref s) => write!(fmt, "bad halfmove clock '{}'", s),
+            FenError::FullmoveNumber(ref s) => write!(fmt, "bad fullmove number '{}'", s),
+        }
+    }
+}
+
+impl Error for FenError {}
+
+/// Parses a lowercase square name such as "e3".
+pub fn parse_square(s: &str) -> Option<BitboardPiece> {
+    let mut chars = s.chars();
+    let file = match chars.next() {
+        Some(c @ 'a'..='h') => c as usize - 'a' as usize,
+        _ => return None,
+    };
+    let rank = match chars.next() {
+        Some(c @ '1'..='8') => c as usize - '1' as usize,
+        _ => return None,
+    };
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(BitboardPiece::from_file_rank(file, rank))
+}
+
+/// Formats a square as a lowercase name such as "e3".
+pub fn square_name(square: BitboardPiece) -> String {
+    format!("{}{}",
+            (b'a' + square.file() as u8) as char,
+            (b'1' + square.rank() as u8) as char)
+}
+
+/// Which of a team's moves a generator produces.
+#[derive(Clone,Copy,Debug)]
+enum Stage {
+    All,
+    /// Captures, including en passant, and promotions.
+    Tactical,
+    /// Everything `Tactical` leaves out.
+    Quiet,
+    /// Every move, but only for a team in check.
+    Evasions,
+}
+
+/// What `do_move` hands back so that `undo_move` can take the move back:
+/// just the parts of the board a move can't be reversed into.
+#[derive(Clone,Copy,Debug)]
+pub struct UndoRecord {
+    mv: ChessMove,
+    captured: Option<Piece>,
+    white_castling: CastlingRights,
+    black_castling: CastlingRights,
+    en_passant: Bitboard,
+    halfmove_clock: u32,
+    zobrist: u64,
+    pawn_zobrist: u64,
+}
+
+/* Equality and hashing only look at the position itself, so the clock
+ * and history don't stop repeated positions from matching. Hashing just
+ * uses the Zobrist key, which do_move keeps up to date.
+ */
+#[derive(Clone,Debug)]
 pub struct ChessBoard {
     pawns: Bitboard,
     knights: Bitboard,
@@ -58,8 +317,49 @@ pub struct ChessBoard {
     bishops: Bitboard,
     queens: Bitboard,
     kings: Bitboard,
-    enemies: Bitboard,
-    allies: Bitboard,
+    black: Bitboard,
+    white: Bitboard,
+    white_castling: CastlingRights,
+    black_castling: CastlingRights,
+    /* The square skipped by a pawn's double push on the last move, if any. */
+    en_passant: Bitboard,
+    white_to_move: bool,
+    /* Plies since the last capture or pawn move. */
+    halfmove_clock: u32,
+    /* Starts at 1 and goes up after every Black move. */
+    fullmove_number: u32,
+    /* Keys of earlier positions, oldest first. Only the last
+     * halfmove_clock of them can repeat, but undo_move needs the rest.
+     */
+    history: Vec<u64>,
+    zobrist: u64,
+    /* The part of the key that comes from pawns alone. */
+    pawn_zobrist: u64,
+}
+
+impl PartialEq for ChessBoard {
+    fn eq(&self, other: &ChessBoard) -> bool {
+        self.pawns == other.pawns &&
+        self.knights == other.knights &&
+        self.rooks == other.rooks &&
+        self.bishops == other.bishops &&
+        self.queens == other.queens &&
+        self.kings == other.kings &&
+        self.black == other.black &&
+        self.white == other.white &&
+        self.white_castling == other.white_castling &&
+        self.black_castling == other.black_castling &&
+        self.en_passant == other.en_passant &&
+        self.white_to_move == other.white_to_move
+    }
+}
+
+impl Eq for ChessBoard {}
+
+impl Hash for ChessBoard {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.zobrist.hash(state);
+    }
 }
 
 impl ChessBoard {
@@ -67,7 +367,7 @@ impl ChessBoard {
         /* NOTE: These look like they're flipped
          * horizontally, but this is intentional.
          */
-        ChessBoard {
+        let mut board = ChessBoard {
             pawns: Bitboard::new(0b00000000,
                                  0b11111111,
                                  0b00000000,
@@ -116,7 +416,7 @@ impl ChessBoard {
                                  0b00000000,
                                  0b00000000,
                                  0b00010000),
-            enemies: Bitboard::new(0b11111111,
+            black: Bitboard::new(0b11111111,
                                    0b11111111,
                                    0b00000000,
                                    0b00000000,
@@ -124,7 +424,7 @@ impl ChessBoard {
                                    0b00000000,
                                    0b00000000,
                                    0b00000000),
-            allies: Bitboard::new(0b00000000,
+            white: Bitboard::new(0b00000000,
                                   0b00000000,
                                   0b00000000,
                                   0b00000000,
@@ -132,20 +432,401 @@ impl ChessBoard {
                                   0b00000000,
                                   0b11111111,
                                   0b11111111),
+            white_castling: CastlingRights::all(),
+            black_castling: CastlingRights::all(),
+            en_passant: Bitboard::from_u64(0),
+            white_to_move: true,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            history: Vec::new(),
+            zobrist: 0,
+            pawn_zobrist: 0,
+        };
+        board.zobrist = board.compute_zobrist();
+        board.pawn_zobrist = board.compute_pawn_zobrist();
+        board
+    }
+
+    /// Reads a position from Forsyth-Edwards Notation. White is always
+    /// the ally. The halfmove clock and fullmove number may be left off.
+    pub fn from_fen(fen: &str) -> Result<ChessBoard, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(FenError::FieldCount(fields.len()));
+        }
+
+        let empty = Bitboard::from_u64(0);
+        let mut board = ChessBoard {
+            pawns: empty,
+            knights: empty,
+            rooks: empty,
+            bishops: empty,
+            queens: empty,
+            kings: empty,
+            black: empty,
+            white: empty,
+            white_castling: CastlingRights {
+                kingside: false,
+                queenside: false,
+            },
+            black_castling: CastlingRights {
+                kingside: false,
+                queenside: false,
+            },
+            en_passant: empty,
+            white_to_move: true,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            history: Vec::new(),
+            zobrist: 0,
+            pawn_zobrist: 0,
+        };
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (row, rank_str) in ranks.iter().enumerate() {
+            let rank = 7 - row;
+            let mut file = 0;
+            for c in rank_str.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    if skip == 0 || skip > 8 {
+                        return Err(FenError::RankLength(rank + 1));
+                    }
+                    file += skip as usize;
+                    continue;
+                }
+
+                let piece = Piece::from_char(c).ok_or(FenError::UnknownPiece(c))?;
+                if file >= 8 {
+                    return Err(FenError::RankLength(rank + 1));
+                }
+                if piece == Piece::Pawn && (rank == 0 || rank == 7) {
+                    return Err(FenError::PawnOnBackRank);
+                }
+
+                let square = BitboardPiece::from_file_rank(file, rank);
+                board.piece_board_mut(piece).add(square);
+                if c.is_uppercase() {
+                    board.white.add(square);
+                } else {
+                    board.black.add(square);
+                }
+                file += 1;
+            }
+            if file != 8 {
+                return Err(FenError::RankLength(rank + 1));
+            }
+        }
+        if board.kings.intersect(board.white).num_pieces() != 1 ||
+           board.kings.intersect(board.black).num_pieces() != 1 {
+            return Err(FenError::KingCount);
+        }
+        /* Move lists only have room for the moves of a real position. */
+        for &side in [board.white, board.black].iter() {
+            let count = |pieces: Bitboard| pieces.intersect(side).num_pieces();
+            /* Pieces beyond the starting set must have been pawns once. */
+            let promoted = count(board.queens).saturating_sub(1) +
+                           count(board.rooks).saturating_sub(2) +
+                           count(board.bishops).saturating_sub(2) +
+                           count(board.knights).saturating_sub(2);
+            if count(side) > 16 || count(board.pawns) + promoted > 8 {
+                return Err(FenError::PieceCount);
+            }
+        }
+
+        board.white_to_move = match fields[1] {
+            "w" => true,
+            "b" => false,
+            s => return Err(FenError::SideToMove(s.to_string())),
+        };
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let right = match c {
+                    'K' => &mut board.white_castling.kingside,
+                    'Q' => &mut board.white_castling.queenside,
+                    'k' => &mut board.black_castling.kingside,
+                    'q' => &mut board.black_castling.queenside,
+                    _ => return Err(FenError::Castling(fields[2].to_string())),
+                };
+                if *right {
+                    return Err(FenError::Castling(fields[2].to_string()));
+                }
+                *right = true;
+            }
+        }
+
+        if fields[3] != "-" {
+            /* The target sits behind the pawn that just moved. */
+            let expected_rank = if board.white_to_move { 5 } else { 2 };
+            match parse_square(fields[3]) {
+                Some(square) if square.rank() == expected_rank => board.en_passant.add(square),
+                _ => return Err(FenError::EnPassant(fields[3].to_string())),
+            }
+        }
+
+        if fields.len() == 6 {
+            board.halfmove_clock = fields[4]
+                .parse()
+                .map_err(|_| FenError::HalfmoveClock(fields[4].to_string()))?;
+            board.fullmove_number = match fields[5].parse() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(FenError::FullmoveNumber(fields[5].to_string())),
+            };
+        }
+
+        board.zobrist = board.compute_zobrist();
+        board.pawn_zobrist = board.compute_pawn_zobrist();
+        Ok(board)
+    }
+
+    /// Writes the position in Forsyth-Edwards Notation, with White as the
+    /// ally.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+
+        for rank in (0..8).rev() {
+            let mut empty_squares = 0;
+            for file in 0..8 {
+                let square = BitboardPiece::from_file_rank(file, rank);
+                match self.piece_at(square) {
+                    Some(piece) => {
+                        if empty_squares > 0 {
+                            fen.push_str(&empty_squares.to_string());
+                            empty_squares = 0;
+                        }
+                        let c = piece.to_char();
+                        if self.white.contains(square) {
+                            fen.push(c);
+                        } else {
+                            fen.push(c.to_ascii_lowercase());
+                        }
+                    }
+                    None => empty_squares += 1,
+                }
+            }
+            if empty_squares > 0 {
+                fen.push_str(&empty_squares.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.white_to_move { " w " } else { " b " });
+
+        let rights = [(self.white_castling.kingside, 'K'),
+                      (self.white_castling.queenside, 'Q'),
+                      (self.black_castling.kingside, 'k'),
+                      (self.black_castling.queenside, 'q')];
+        let castling: String = rights.iter().filter(|r| r.0).map(|r| r.1).collect();
+        if castling.is_empty() {
+            fen.push('-');
+        } else {
+            fen.push_str(&castling);
+        }
+
+        fen.push(' ');
+        match self.en_passant.pieces().next() {
+            Some(square) => fen.push_str(&square_name(square)),
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    /// Returns the kind of piece standing on `square`, if any.
+    pub fn piece_at(&self, square: BitboardPiece) -> Option<Piece> {
+        if self.pawns.contains(square) {
+            Some(Piece::Pawn)
+        } else if self.knights.contains(square) {
+            Some(Piece::Knight)
+        } else if self.bishops.contains(square) {
+            Some(Piece::Bishop)
+        } else if self.rooks.contains(square) {
+            Some(Piece::Rook)
+        } else if self.queens.contains(square) {
+            Some(Piece::Queen)
+        } else if self.kings.contains(square) {
+            Some(Piece::King)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the team whose turn it is.
+    pub fn turn(&self) -> Team {
+        if self.white_to_move {
+            Team::Ally
+        } else {
+            Team::Enemy
+        }
+    }
+
+    /// Generates the legal moves for the team whose turn it is.
+    pub fn legal_moves(&self) -> MoveList {
+        self.gen_legal_moves(self.turn())
+    }
+
+    /// Returns whether the king of the team to move is in check.
+    pub fn in_check(&self) -> bool {
+        self.king_in_check(self.turn())
+    }
+
+    /// Generates the legal captures and promotions for `us`.
+    pub fn tactical_moves(&self, us: Team) -> MoveList {
+        self.gen_moves(us, Stage::Tactical)
+    }
+
+    /// Generates the legal moves for `us` that neither capture nor promote.
+    pub fn quiet_moves(&self, us: Team) -> MoveList {
+        self.gen_moves(us, Stage::Quiet)
+    }
+
+    /// Generates the legal moves that get `us` out of check, or nothing
+    /// if they aren't in check. Only the king, blockers and pieces that
+    /// can take the checker are looked at.
+    pub fn evasions(&self, us: Team) -> MoveList {
+        self.gen_moves(us, Stage::Evasions)
+    }
+
+    /// Decides whether the game is over from the legal moves of the team
+    /// to move and whether its king is in check, then checks for draws.
+    pub fn outcome(&self) -> GameOutcome {
+        if self.legal_moves().is_empty() {
+            if self.in_check() {
+                GameOutcome::Checkmate(self.turn().other_team())
+            } else {
+                GameOutcome::Stalemate
+            }
+        } else if self.is_insufficient_material() {
+            GameOutcome::Draw(DrawReason::InsufficientMaterial)
+        } else if self.halfmove_clock >= 100 {
+            GameOutcome::Draw(DrawReason::FiftyMoveRule)
+        } else if self.repetitions() >= 3 {
+            GameOutcome::Draw(DrawReason::ThreefoldRepetition)
+        } else {
+            GameOutcome::Ongoing
         }
     }
 
-    pub fn as_other(self) -> ChessBoard {
+    /// Returns how many times the current position has occurred,
+    /// counting this one.
+    pub fn repetitions(&self) -> usize {
+        1 + self.repeatable_history().iter()
+            .filter(|&&key| key == self.zobrist)
+            .count()
+    }
+
+    /// Copies the board with only the history that can still repeat. The
+    /// search clones a board for every node it visits, so copying the
+    /// whole game each time would add up, and it never undoes a move it
+    /// didn't make itself. Undoing past the copy loses repetitions.
+    pub fn clone_for_search(&self) -> ChessBoard {
         ChessBoard {
-            pawns: self.pawns.flip_vertical(),
-            rooks: self.rooks.flip_vertical(),
-            knights: self.knights.flip_vertical(),
-            bishops: self.bishops.flip_vertical(),
-            queens: self.queens.flip_vertical(),
-            kings: self.kings.flip_vertical(),
-            enemies: self.allies.flip_vertical(),
-            allies: self.enemies.flip_vertical(),
+            pawns: self.pawns,
+            knights: self.knights,
+            rooks: self.rooks,
+            bishops: self.bishops,
+            queens: self.queens,
+            kings: self.kings,
+            black: self.black,
+            white: self.white,
+            white_castling: self.white_castling,
+            black_castling: self.black_castling,
+            en_passant: self.en_passant,
+            white_to_move: self.white_to_move,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            history: self.repeatable_history().to_vec(),
+            zobrist: self.zobrist,
+            pawn_zobrist: self.pawn_zobrist,
+        }
+    }
+
+    /// The keys of the earlier positions that could still come up again:
+    /// those since the last capture or pawn move.
+    fn repeatable_history(&self) -> &[u64] {
+        let len = self.history.len();
+        &self.history[len - len.min(self.halfmove_clock as usize)..]
+    }
+
+    /// Returns the position's 64-bit Zobrist key, which covers the pieces,
+    /// the team to move, castling rights and the en passant target. Equal
+    /// positions always share a key, so it can stand in for the position
+    /// in search tables and opening books.
+    pub fn zobrist_key(&self) -> u64 {
+        self.zobrist
+    }
+
+    /// Returns a Zobrist key covering only where each team's pawns are,
+    /// for caching anything worked out from the pawns alone.
+    pub fn pawn_key(&self) -> u64 {
+        self.pawn_zobrist
+    }
+
+    /// Works out the Zobrist key from scratch. do_move updates the key as
+    /// it goes instead, so this is only needed for new boards and checks.
+    fn compute_zobrist(&self) -> u64 {
+        let mut key = self.castling_zobrist();
+        for square in self.white.union(self.black).pieces() {
+            if let Some(piece) = self.piece_at(square) {
+                key ^= zobrist::piece(self.white.contains(square), piece, square);
+            }
+        }
+        for square in self.en_passant.pieces() {
+            key ^= zobrist::en_passant(square);
+        }
+        if !self.white_to_move {
+            key ^= zobrist::black_to_move();
         }
+        key
+    }
+
+    fn compute_pawn_zobrist(&self) -> u64 {
+        self.pawns.pieces()
+            .map(|square| zobrist::piece(self.white.contains(square), Piece::Pawn, square))
+            .fold(0, |key, k| key ^ k)
+    }
+
+    fn castling_zobrist(&self) -> u64 {
+        zobrist::castling([self.white_castling.kingside,
+                           self.white_castling.queenside,
+                           self.black_castling.kingside,
+                           self.black_castling.queenside])
+    }
+
+    /// Xors the piece on `square`, if any, in or out of the key, and out
+    /// of the pawn key too if it's a pawn.
+    fn toggle_zobrist(&mut self, square: BitboardPiece) {
+        if let Some(piece) = self.piece_at(square) {
+            let key = zobrist::piece(self.white.contains(square), piece, square);
+            self.zobrist ^= key;
+            if piece == Piece::Pawn {
+                self.pawn_zobrist ^= key;
+            }
+        }
+    }
+
+    /// Returns whether neither side has enough material left to mate:
+    /// bare kings, a single minor piece, or only bishops on one colour.
+    fn is_insufficient_material(&self) -> bool {
+        if !self.pawns.union(self.rooks).union(self.queens).is_empty() {
+            return false;
+        }
+
+        let minors = self.knights.union(self.bishops);
+        if minors.num_pieces() <= 1 {
+            return true;
+        }
+
+        let dark_squares = Bitboard::from_u64(0x55AA55AA55AA55AA);
+        self.knights.is_empty() &&
+        (self.bishops.intersect(dark_squares).is_empty() ||
+         self.bishops.intersect(dark_squares.complement()).is_empty())
     }
 
     pub fn print(&self) {
@@ -162,9 +843,9 @@ impl ChessBoard {
                     Color::Black
                 };
 
-                if self.allies.contains(piece) {
+                if self.white.contains(piece) {
                     print!("{}", get_color_escape_code(Color::Green, background_color));
-                } else if self.enemies.contains(piece) {
+                } else if self.black.contains(piece) {
                     print!("{}", get_color_escape_code(Color::Red, background_color));
                 } else {
                     print!("{}", get_color_escape_code(Color::White, background_color));
@@ -217,6 +898,14 @@ impl ChessBoard {
         let to_file = chars.next().and_then(|c| alpha_to_file(c)).ok_or(())?;
         let to_rank = chars.next().and_then(|c| c.to_digit(10)).ok_or(())? - 1;
 
+        let promotion = match chars.next() {
+            Some(c) => match Piece::from_char(c) {
+                Some(Piece::Pawn) | Some(Piece::King) | None => return Err(()),
+                piece => piece,
+            },
+            None => None,
+        };
+
         match chars.next() {
             Some(_) => return Err(()),
             None => {}
@@ -226,193 +915,1395 @@ impl ChessBoard {
             Ok(ChessMove {
                 from: BitboardPiece::from_file_rank(from_file as usize, from_rank as usize),
                 to: BitboardPiece::from_file_rank(to_file as usize, to_rank as usize),
+                promotion,
             })
         } else {
             Err(())
         }
     }
 
-    fn gen_pawn_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for pawn in self.pawns.intersect(self.allies).pieces() {
-            try_move!(moves, self.enemies, pawn, -1, 1);
-            try_move!(moves,
-                      self.enemies.union(self.allies).complement(),
-                      pawn,
-                      0,
-                      1);
-            try_move!(moves, self.enemies, pawn, 1, 1);
-            if Bitboard::from_u64(0x000000000000FF00).contains(pawn) {
-                try_move!(moves,
-                    self.enemies.union(self.allies).complement(),
-                    pawn,
-                    0,
-                    2);
+    /// Returns the pieces belonging to `team`. White is always the ally.
+    fn side(&self, team: Team) -> Bitboard {
+        match team {
+            Team::Ally => self.white,
+            Team::Enemy => self.black,
+        }
+    }
+
+    /// Returns where `team`'s pieces of type `piece` stand.
+    pub fn pieces_of(&self, team: Team, piece: Piece) -> Bitboard {
+        self.piece_board(piece).intersect(self.side(team))
+    }
+
+    /// Returns every square with a piece on it.
+    pub fn occupied(&self) -> Bitboard {
+        self.white.union(self.black)
+    }
+
+    fn king_square(&self, team: Team) -> Option<BitboardPiece> {
+        self.kings.intersect(self.side(team)).pieces().next()
+    }
+
+    /// Returns the pieces of team `by` that attack `square`, whoever
+    /// stands on it. Pieces behind another attacker don't count.
+    pub fn attackers_to(&self, square: BitboardPiece, by: Team) -> Bitboard {
+        self.attackers(square, by, self.white.union(self.black))
+    }
+
+    /// Returns whether any piece of team `by` attacks `square`.
+    pub fn is_attacked(&self, square: BitboardPiece, by: Team) -> bool {
+        !self.attackers_to(square, by).is_empty()
+    }
+
+    /// Returns the pieces of team `by` attacking `square`, treating only
+    /// the squares in `occupied` as blocking.
+    fn attackers(&self, square: BitboardPiece, by: Team, occupied: Bitboard) -> Bitboard {
+        let theirs = self.side(by);
+        let straight = self.rooks.union(self.queens);
+        let diagonal = self.bishops.union(self.queens);
+
+        /* A pawn attacks `square` from where one of ours would attack it. */
+        let attackers = magic::rook_attacks(square, occupied).intersect(straight)
+            .union(magic::bishop_attacks(square, occupied).intersect(diagonal))
+            .union(attacks::knight_attacks(square).intersect(self.knights))
+            .union(attacks::king_attacks(square).intersect(self.kings))
+            .union(attacks::pawn_attacks(by.other_team(), square).intersect(self.pawns));
+
+        /* A piece standing on the square itself doesn't attack it. */
+        let mut target = Bitboard::from_u64(0);
+        target.add(square);
+        attackers.intersect(theirs).intersect(occupied).intersect(target.complement())
+    }
+
+    /// Works out how much material `mv` wins, in centipawns, once every piece
+    /// that can join in on its target square has, cheapest first. Either
+    /// team may stop taking back whenever carrying on would lose more.
+    /// Sliders lined up behind another attacker join in once it moves off
+    /// the line, since the attackers are found again after every capture.
+    pub fn see(&self, mv: &ChessMove) -> i32 {
+        let mut occupied = self.white.union(self.black);
+        let mover = match self.piece_at(mv.from) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+        let mut side = if self.white.contains(mv.from) { Team::Enemy } else { Team::Ally };
+
+        /* gains[i] is what the ith capture wins, if the exchange stopped
+         * right after it.
+         */
+        let mut gains = [0; 32];
+        gains[0] = match self.piece_at(mv.to) {
+            Some(piece) => piece.value(),
+            None if mover == Piece::Pawn && mv.from.file() != mv.to.file() => {
+                occupied.remove(BitboardPiece::from_file_rank(mv.to.file(), mv.from.rank()));
+                Piece::Pawn.value()
             }
+            None => 0,
+        };
+        let mut on_target = mover;
+        if let Some(piece) = mv.promotion {
+            gains[0] += piece.value() - Piece::Pawn.value();
+            on_target = piece;
         }
-        moves
+        occupied.remove(mv.from);
+
+        let mut depth = 0;
+        while depth + 1 < gains.len() {
+            let attackers = self.attackers(mv.to, side, occupied);
+            let cheapest = [Piece::Pawn, Piece::Knight, Piece::Bishop,
+                            Piece::Rook, Piece::Queen, Piece::King]
+                .iter()
+                .filter_map(|&piece| {
+                    self.piece_board(piece).intersect(attackers).pieces().next()
+                        .map(|square| (square, piece))
+                })
+                .next();
+            let (square, piece) = match cheapest {
+                Some(attacker) => attacker,
+                None => break,
+            };
+            occupied.remove(square);
+            /* A king can only take back if nothing would take it. */
+            if piece == Piece::King &&
+               !self.attackers(mv.to, side.other_team(), occupied).is_empty() {
+                break;
+            }
+
+            depth += 1;
+            gains[depth] = on_target.value() - gains[depth - 1];
+            on_target = piece;
+            side = side.other_team();
+        }
+
+        /* Go back through the exchange, letting each team stop where it
+         * does best.
+         */
+        while depth > 0 {
+            gains[depth - 1] = -(-gains[depth - 1]).max(gains[depth]);
+            depth -= 1;
+        }
+        gains[0]
     }
 
-    fn gen_rook_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for rook in self.rooks.intersect(self.allies).pieces() {
-            for to in self.allies
-                .union(self.enemies)
-                .horizontal_ray(rook)
-                .union(self.allies.union(self.enemies).vertical_ray(rook))
-                .intersect(self.allies.complement())
-                .pieces() {
-                moves.push(ChessMove {
-                    from: rook,
-                    to: to,
-                });
+    fn king_in_check(&self, team: Team) -> bool {
+        match self.king_square(team) {
+            Some(king) => self.is_attacked(king, team.other_team()),
+            None => false,
+        }
+    }
+
+    /// Generates pawn moves for the whole set of pawns at once by
+    /// shifting bitboards, then splits them up into moves. Captures only
+    /// land on squares in `captures`, and pushes on squares in `pushes`.
+    fn gen_pawn_moves(&self, us: Team, captures: u64, pushes: u64, moves: &mut MoveList) {
+        let pawns = bits(self.pawns.intersect(self.side(us)));
+        let empty = bits(self.white.union(self.black).complement());
+        let theirs = bits(self.side(us.other_team()));
+
+        /* Only a double push by the other team leaves an en passant
+         * target, on the third rank from their side.
+         */
+        let (single_pushes, double_pushes, left_captures, right_captures, forward, last_rank) =
+            match us {
+                Team::Ally => {
+                    let targets = (theirs | (bits(self.en_passant) & attacks::RANK_6)) & captures;
+                    let single_pushes = (pawns << 8) & empty;
+                    (single_pushes & pushes,
+                     ((single_pushes & attacks::RANK_3) << 8) & empty & pushes,
+                     ((pawns & !attacks::FILE_A) << 7) & targets,
+                     ((pawns & !attacks::FILE_H) << 9) & targets,
+                     1,
+                     7)
+                }
+                Team::Enemy => {
+                    let targets = (theirs | (bits(self.en_passant) & attacks::RANK_3)) & captures;
+                    let single_pushes = (pawns >> 8) & empty;
+                    (single_pushes & pushes,
+                     ((single_pushes & attacks::RANK_6) >> 8) & empty & pushes,
+                     ((pawns & !attacks::FILE_A) >> 9) & targets,
+                     ((pawns & !attacks::FILE_H) >> 7) & targets,
+                     -1,
+                     0)
+                }
+            };
+
+        let sets = [(left_captures, -1, forward),
+                    (single_pushes, 0, forward),
+                    (right_captures, 1, forward),
+                    (double_pushes, 0, 2 * forward)];
+        for &(set, x, y) in sets.iter() {
+            for to in Bitboard::from_u64(set).pieces() {
+                let mv = ChessMove {
+                    from: to.shift(-x, -y),
+                    to,
+                    promotion: None,
+                };
+                if to.rank() == last_rank {
+                    moves.extend(PROMOTIONS.iter().map(|&piece| ChessMove {
+                        promotion: Some(piece),
+                        ..mv
+                    }));
+                } else {
+                    moves.push(mv);
+                }
             }
         }
-        moves
     }
 
-    fn gen_bishop_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for bishop in self.bishops.intersect(self.allies).pieces() {
-            for to in self.allies
-                .union(self.enemies)
-                .diagonal_ray(bishop)
-                .union(self.allies.union(self.enemies).anti_diagonal_ray(bishop))
-                .intersect(self.allies.complement())
-                .pieces() {
+    /// Generates the moves of every piece of `us` found in `pieces`, which
+    /// attack the squares `attacks` gives for them, onto `targets`.
+    fn gen_piece_moves<F>(&self, us: Team, pieces: Bitboard, attacks: F, targets: Bitboard,
+                          moves: &mut MoveList)
+        where F: Fn(BitboardPiece) -> Bitboard
+    {
+        for from in pieces.intersect(self.side(us)).pieces() {
+            for to in attacks(from).intersect(targets).pieces() {
                 moves.push(ChessMove {
-                    from: bishop,
-                    to: to,
+                    from,
+                    to,
+                    promotion: None,
                 });
             }
         }
-        moves
     }
 
-    fn gen_queen_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for queen in self.queens.intersect(self.allies).pieces() {
-            for to in self.allies
-                .union(self.enemies)
-                .horizontal_ray(queen)
-                .union(self.allies.union(self.enemies).vertical_ray(queen))
-                .union(self.allies.union(self.enemies).diagonal_ray(queen))
-                .union(self.allies.union(self.enemies).anti_diagonal_ray(queen))
-                .intersect(self.allies.complement())
-                .pieces() {
-                moves.push(ChessMove {
-                    from: queen,
-                    to: to,
-                });
+    /// Generates the moves for `us` that `stage` asks for, without
+    /// checking whether they leave the king attacked.
+    fn gen_pseudo_legal_moves(&self, us: Team, stage: Stage) -> MoveList {
+        let mut moves = MoveList::new();
+        let occupied = self.white.union(self.black);
+        let ours = self.side(us);
+        let theirs = self.side(us.other_team());
+        let last_rank = match us {
+            Team::Ally => attacks::RANK_8,
+            Team::Enemy => attacks::RANK_1,
+        };
+
+        /* Where the king may go, where the other pieces may go, and where
+         * pawns may capture and push to.
+         */
+        let (king_targets, targets, pawn_captures, pawn_pushes) = match stage {
+            Stage::All => (ours.complement(), ours.complement(), !0, !0),
+            Stage::Tactical => (theirs, theirs, !0, last_rank),
+            Stage::Quiet => (occupied.complement(), occupied.complement(), 0, !last_rank),
+            Stage::Evasions => {
+                let king = match self.king_square(us) {
+                    Some(king) => king,
+                    None => return moves,
+                };
+                let checkers = self.attackers(king, us.other_team(), occupied);
+                /* In double check only the king can move. Otherwise
+                 * capture the checker or step in front of it; a pawn can
+                 * also take a checking pawn en passant.
+                 */
+                let blocks = match checkers.num_pieces() {
+                    0 => return moves,
+                    1 => checkers.union(between(king, checkers.pieces().next().unwrap())),
+                    _ => Bitboard::from_u64(0),
+                };
+                let en_passant = if checkers.intersect(self.pawns).is_empty() {
+                    0
+                } else {
+                    bits(self.en_passant)
+                };
+                (ours.complement(), blocks, bits(blocks) | en_passant, bits(blocks))
             }
+        };
+
+        self.gen_pawn_moves(us, pawn_captures, pawn_pushes, &mut moves);
+        self.gen_piece_moves(us, self.kings, attacks::king_attacks, king_targets, &mut moves);
+        if let Stage::All | Stage::Quiet = stage {
+            self.gen_castling_moves(us, &mut moves);
         }
+        self.gen_piece_moves(us, self.knights, attacks::knight_attacks, targets, &mut moves);
+        self.gen_piece_moves(us, self.bishops, |from| magic::bishop_attacks(from, occupied),
+                             targets, &mut moves);
+        self.gen_piece_moves(us, self.queens, |from| magic::queen_attacks(from, occupied),
+                             targets, &mut moves);
+        self.gen_piece_moves(us, self.rooks, |from| magic::rook_attacks(from, occupied),
+                             targets, &mut moves);
         moves
     }
 
-    fn gen_knight_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for knight in self.knights.intersect(self.allies).pieces() {
-            try_move!(moves, self.allies.complement(), knight, 2, 1);
-            try_move!(moves, self.allies.complement(), knight, 1, 2);
-            try_move!(moves, self.allies.complement(), knight, -1, 2);
-            try_move!(moves, self.allies.complement(), knight, -2, 1);
-            try_move!(moves, self.allies.complement(), knight, -2, -1);
-            try_move!(moves, self.allies.complement(), knight, -1, -2);
-            try_move!(moves, self.allies.complement(), knight, 1, -2);
-            try_move!(moves, self.allies.complement(), knight, 2, -1);
+    fn gen_legal_moves(&self, us: Team) -> MoveList {
+        self.gen_moves(us, Stage::All)
+    }
+
+    /// Generates the moves for `us` that `stage` asks for and that don't
+    /// leave their king attacked.
+    ///
+    /// Rather than playing out every move, this finds the pieces giving
+    /// check and the pieces pinned to the king up front, then only keeps
+    /// moves that stay on their pin line and answer any check.
+    fn gen_moves(&self, us: Team, stage: Stage) -> MoveList {
+        let mut moves = self.gen_pseudo_legal_moves(us, stage);
+        let king = match self.king_square(us) {
+            Some(king) => king,
+            None => return moves,
+        };
+        let them = us.other_team();
+
+        let occupied = self.white.union(self.black);
+        let mut without_king = occupied;
+        without_king.remove(king);
+
+        let checkers = self.attackers(king, them, occupied);
+        let num_checkers = checkers.num_pieces();
+        /* Squares which capture or block the (single) checking piece. */
+        let mut evasions = checkers;
+        for checker in checkers.pieces() {
+            evasions = evasions.union(between(king, checker));
         }
+        let pinned = self.pinned_pieces(king, us);
+
+        moves.retain(|mv| {
+            if mv.from == king {
+                /* Castling already checked every square the king crosses. */
+                let is_castle = (mv.from.file() as i32 - mv.to.file() as i32).abs() == 2;
+                return is_castle || self.attackers(mv.to, them, without_king).is_empty();
+            }
+
+            if self.pawns.contains(mv.from) && self.en_passant.contains(mv.to) &&
+               mv.from.file() != mv.to.file() {
+                /* En passant empties two squares on one rank, which can
+                 * uncover a slider in ways pins don't describe. Just look
+                 * at the board as it would be afterwards.
+                 */
+                let mut after = occupied;
+                after.remove(mv.from);
+                after.remove(BitboardPiece::from_file_rank(mv.to.file(), mv.from.rank()));
+                after.add(mv.to);
+                return self.attackers(king, them, after).is_empty();
+            }
+
+            if num_checkers > 1 || (num_checkers == 1 && !evasions.contains(mv.to)) {
+                return false;
+            }
+
+            !pinned.contains(mv.from) || line_through(king, mv.from).contains(mv.to)
+        });
+
         moves
     }
 
-    fn gen_king_moves(&self) -> Vec<ChessMove> {
-        let mut moves = Vec::new();
-        for king in self.kings.intersect(self.allies).pieces() {
-            try_move!(moves, self.allies.complement(), king, 0, 1);
-            try_move!(moves, self.allies.complement(), king, 0, -1);
-            try_move!(moves, self.allies.complement(), king, -1, 0);
-            try_move!(moves, self.allies.complement(), king, 1, 0);
-            try_move!(moves, self.allies.complement(), king, 1, 1);
-            try_move!(moves, self.allies.complement(), king, -1, -1);
-            try_move!(moves, self.allies.complement(), king, -1, 1);
-            try_move!(moves, self.allies.complement(), king, 1, -1);
+    /// Returns the pieces of `us` that can't leave the line between
+    /// `king` and a slider of the other team without exposing the king.
+    fn pinned_pieces(&self, king: BitboardPiece, us: Team) -> Bitboard {
+        let ours = self.side(us);
+        let theirs = self.side(us.other_team());
+        let straight = self.rooks.union(self.queens).intersect(theirs);
+        let diagonal = self.bishops.union(self.queens).intersect(theirs);
+        let mut pinned = Bitboard::from_u64(0);
+
+        for &(x, y) in KING_OFFSETS.iter() {
+            let sliders = if x == 0 || y == 0 { straight } else { diagonal };
+            let mut candidate = None;
+            let mut square = king;
+
+            while let Some(next) = offset(square, x, y) {
+                square = next;
+                if ours.contains(square) {
+                    if candidate.is_some() {
+                        break;
+                    }
+                    candidate = Some(square);
+                } else if theirs.contains(square) {
+                    if let Some(piece) = candidate {
+                        if sliders.contains(square) {
+                            pinned.add(piece);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        pinned
+    }
+
+    fn gen_castling_moves(&self, us: Team, moves: &mut MoveList) {
+        let (rank, rights) = match us {
+            Team::Ally => (0, self.white_castling),
+            Team::Enemy => (7, self.black_castling),
+        };
+        let them = us.other_team();
+        let square = |file| BitboardPiece::from_file_rank(file, rank);
+        let king = square(4);
+        let rooks = self.rooks.intersect(self.side(us));
+        let occupied = self.white.union(self.black);
+
+        if !self.kings.intersect(self.side(us)).contains(king) || self.is_attacked(king, them) {
+            return;
+        }
+
+        if rights.kingside &&
+           rooks.contains(square(7)) &&
+           [5, 6].iter().all(|&f| !occupied.contains(square(f)) &&
+                                  !self.is_attacked(square(f), them)) {
+            moves.push(ChessMove {
+                from: king,
+                to: square(6),
+                promotion: None,
+            });
+        }
+
+        if rights.queenside &&
+           rooks.contains(square(0)) &&
+           [1, 2, 3].iter().all(|&f| !occupied.contains(square(f))) &&
+           [2, 3].iter().all(|&f| !self.is_attacked(square(f), them)) {
+            moves.push(ChessMove {
+                from: king,
+                to: square(2),
+                promotion: None,
+            });
+        }
+    }
+
+    /// Plays a move for the team whose turn it is, returning what
+    /// `undo_move` needs to take it back again.
+    pub fn do_move(&mut self, mv: &ChessMove) -> UndoRecord {
+        let is_castle = self.kings.contains(mv.from) &&
+                        (mv.from.file() as i32 - mv.to.file() as i32).abs() == 2;
+        let is_pawn = self.pawns.contains(mv.from);
+        let is_en_passant = is_pawn && self.en_passant.contains(mv.to) &&
+                            mv.from.file() != mv.to.file();
+        let is_double_push = is_pawn &&
+                             (mv.from.rank() as i32 - mv.to.rank() as i32).abs() == 2;
+
+        let undo = UndoRecord {
+            mv: *mv,
+            captured: if is_en_passant { Some(Piece::Pawn) } else { self.piece_at(mv.to) },
+            white_castling: self.white_castling,
+            black_castling: self.black_castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            zobrist: self.zobrist,
+            pawn_zobrist: self.pawn_zobrist,
+        };
+
+        /* Nothing before a capture or pawn move can ever repeat. */
+        if is_pawn || undo.captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        self.history.push(self.zobrist);
+        let castling_zobrist = self.castling_zobrist();
+
+        self.move_piece(mv.from, mv.to);
+
+        if is_en_passant {
+            /* The captured pawn sits beside us, not on the target square. */
+            self.remove_piece(BitboardPiece::from_file_rank(mv.to.file(), mv.from.rank()));
+        }
+
+        if let Some(piece) = mv.promotion {
+            self.toggle_zobrist(mv.to);
+            self.pawns.remove(mv.to);
+            self.piece_board_mut(piece).add(mv.to);
+            self.toggle_zobrist(mv.to);
+        }
+
+        for square in self.en_passant.pieces() {
+            self.zobrist ^= zobrist::en_passant(square);
+        }
+        self.en_passant = Bitboard::from_u64(0);
+        if is_double_push {
+            let target = BitboardPiece::from_file_rank(mv.from.file(),
+                                                       (mv.from.rank() + mv.to.rank()) / 2);
+            self.en_passant.add(target);
+            self.zobrist ^= zobrist::en_passant(target);
+        }
+
+        if is_castle {
+            /* The king already moved, so bring the rook around it. */
+            let rank = mv.from.rank();
+            if mv.to.file() == 6 {
+                self.move_piece(BitboardPiece::from_file_rank(7, rank),
+                                BitboardPiece::from_file_rank(5, rank));
+            } else {
+                self.move_piece(BitboardPiece::from_file_rank(0, rank),
+                                BitboardPiece::from_file_rank(3, rank));
+            }
+        }
+
+        self.clear_castling_rights(mv.from);
+        self.clear_castling_rights(mv.to);
+        self.zobrist ^= castling_zobrist ^ self.castling_zobrist();
+        if !self.white_to_move {
+            self.fullmove_number += 1;
+        }
+        self.white_to_move = !self.white_to_move;
+        self.zobrist ^= zobrist::black_to_move();
+
+        debug_assert_eq!(self.zobrist, self.compute_zobrist(),
+                         "incremental Zobrist key drifted after {}", mv);
+        debug_assert_eq!(self.pawn_zobrist, self.compute_pawn_zobrist(),
+                         "incremental pawn key drifted after {}", mv);
+        undo
+    }
+
+    /// Takes back the last move played, given the record `do_move`
+    /// returned for it.
+    pub fn undo_move(&mut self, undo: &UndoRecord) {
+        let mv = undo.mv;
+        self.white_to_move = !self.white_to_move;
+        if !self.white_to_move {
+            self.fullmove_number -= 1;
+        }
+
+        if self.kings.contains(mv.to) && (mv.from.file() as i32 - mv.to.file() as i32).abs() == 2 {
+            let rank = mv.from.rank();
+            if mv.to.file() == 6 {
+                self.move_piece(BitboardPiece::from_file_rank(5, rank),
+                                BitboardPiece::from_file_rank(7, rank));
+            } else {
+                self.move_piece(BitboardPiece::from_file_rank(3, rank),
+                                BitboardPiece::from_file_rank(0, rank));
+            }
+        }
+
+        if let Some(piece) = mv.promotion {
+            self.piece_board_mut(piece).remove(mv.to);
+            self.pawns.add(mv.to);
         }
+
+        self.move_piece(mv.to, mv.from);
+
+        if let Some(piece) = undo.captured {
+            let is_en_passant = self.pawns.contains(mv.from) && undo.en_passant.contains(mv.to) &&
+                                mv.from.file() != mv.to.file();
+            let square = if is_en_passant {
+                BitboardPiece::from_file_rank(mv.to.file(), mv.from.rank())
+            } else {
+                mv.to
+            };
+            self.piece_board_mut(piece).add(square);
+            if self.white_to_move {
+                self.black.add(square);
+            } else {
+                self.white.add(square);
+            }
+        }
+
+        self.white_castling = undo.white_castling;
+        self.black_castling = undo.black_castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.zobrist = undo.zobrist;
+        self.pawn_zobrist = undo.pawn_zobrist;
+        self.history.pop();
+    }
+
+    /// Generates every legal move for `us` in the order a search should
+    /// try them: captures and promotions that don't lose material, best
+    /// first, then the quiet moves, then the captures that lose material.
+    pub fn gen_ordered_moves(&self, us: Team) -> Vec<ChessMove> {
+        if self.king_in_check(us) {
+            return self.evasions(us).to_vec();
+        }
+        let mut tactical = self.tactical_moves(us);
+        let winning = tactical.sort_by_score(|mv| self.see(mv));
+        let quiet = self.quiet_moves(us);
+
+        let mut moves = Vec::with_capacity(tactical.len() + quiet.len());
+        moves.extend_from_slice(&tactical[..winning]);
+        moves.extend_from_slice(&quiet);
+        moves.extend_from_slice(&tactical[winning..]);
         moves
     }
+
+    fn piece_board(&self, piece: Piece) -> Bitboard {
+        match piece {
+            Piece::Pawn => self.pawns,
+            Piece::Knight => self.knights,
+            Piece::Bishop => self.bishops,
+            Piece::Rook => self.rooks,
+            Piece::Queen => self.queens,
+            Piece::King => self.kings,
+        }
+    }
+
+    fn piece_board_mut(&mut self, piece: Piece) -> &mut Bitboard {
+        match piece {
+            Piece::Pawn => &mut self.pawns,
+            Piece::Knight => &mut self.knights,
+            Piece::Bishop => &mut self.bishops,
+            Piece::Rook => &mut self.rooks,
+            Piece::Queen => &mut self.queens,
+            Piece::King => &mut self.kings,
+        }
+    }
+
+    fn remove_piece(&mut self, square: BitboardPiece) {
+        self.toggle_zobrist(square);
+        for board in [&mut self.pawns,
+                      &mut self.rooks,
+                      &mut self.knights,
+                      &mut self.bishops,
+                      &mut self.queens,
+                      &mut self.kings,
+                      &mut self.black,
+                      &mut self.white]
+            .iter_mut() {
+            board.remove(square);
+        }
+    }
+
+    fn move_piece(&mut self, from: BitboardPiece, to: BitboardPiece) {
+        self.toggle_zobrist(to);
+        self.toggle_zobrist(from);
+        for board in [&mut self.pawns,
+                      &mut self.rooks,
+                      &mut self.knights,
+                      &mut self.bishops,
+                      &mut self.queens,
+                      &mut self.kings,
+                      &mut self.black,
+                      &mut self.white]
+            .iter_mut() {
+            board.remove(to);
+            if board.contains(from) {
+                board.remove(from);
+                board.add(to);
+            }
+        }
+        self.toggle_zobrist(to);
+    }
+
+    /// Clears any castling rights that depend on a king or rook
+    /// which has just left (or been captured on) `square`.
+    fn clear_castling_rights(&mut self, square: BitboardPiece) {
+        let rights = match square.rank() {
+            0 => &mut self.white_castling,
+            7 => &mut self.black_castling,
+            _ => return,
+        };
+
+        match square.file() {
+            0 => rights.queenside = false,
+            4 => {
+                rights.kingside = false;
+                rights.queenside = false;
+            }
+            7 => rights.kingside = false,
+            _ => {}
+        }
+    }
 }
 
-impl Board for ChessBoard {
-    type Move = ChessMove;
+#[cfg(test)]
+fn play(board: &mut ChessBoard, moves: &[&str]) {
+    for s in moves {
+        let mv = board.move_from_str(s).unwrap();
+        assert!(board.legal_moves().contains(&mv), "{} is illegal in {}", s, board.to_fen());
+        board.do_move(&mv);
+    }
+}
 
-    fn gen_ally_moves(&self) -> Vec<Self::Move> {
-        let mut moves = Vec::new();
-        moves.extend(self.gen_pawn_moves());
-        moves.extend(self.gen_king_moves());
-        moves.extend(self.gen_knight_moves());
-        moves.extend(self.gen_bishop_moves());
-        moves.extend(self.gen_queen_moves());
-        moves.extend(self.gen_rook_moves());
-        moves
+#[test]
+fn test_castling_kingside() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["G1F3", "A7A6", "G2G3", "A6A5", "F1G2", "H7H6"]);
+
+    let castle = board.move_from_str("E1G1").unwrap();
+    assert!(board.gen_ordered_moves(Team::Ally).contains(&castle));
+    board.do_move(&castle);
+
+    assert!(board.kings.contains(BitboardPiece::from_file_rank(6, 0)));
+    assert!(board.rooks.contains(BitboardPiece::from_file_rank(5, 0)));
+    assert!(!board.rooks.contains(BitboardPiece::from_file_rank(7, 0)));
+    assert_eq!(board.white_castling, CastlingRights { kingside: false, queenside: false });
+    assert_eq!(board.black_castling, CastlingRights::all());
+}
+
+#[test]
+fn test_castling_enemy_queenside() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4", "B8C6", "D2D4", "D7D6", "G1F3", "C8E6", "F1E2", "D8D7", "A2A3"]);
+
+    let castle = board.move_from_str("E8C8").unwrap();
+    assert!(board.gen_ordered_moves(Team::Enemy).contains(&castle));
+    board.do_move(&castle);
+
+    assert!(board.kings.contains(BitboardPiece::from_file_rank(2, 7)));
+    assert!(board.rooks.contains(BitboardPiece::from_file_rank(3, 7)));
+    assert!(board.black.contains(BitboardPiece::from_file_rank(3, 7)));
+}
+
+#[test]
+fn test_castling_rights_lost_by_rook_move() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["G1F3", "A7A6", "G2G3", "A6A5", "F1G2", "B7B6", "H1G1", "B6B5", "G1H1",
+                       "B5B4"]);
+
+    let castle = board.move_from_str("E1G1").unwrap();
+    assert!(!board.white_castling.kingside);
+    assert!(board.white_castling.queenside);
+    assert!(!board.gen_ordered_moves(Team::Ally).contains(&castle));
+}
+
+#[cfg(test)]
+fn castles(fen: &str) -> Vec<String> {
+    let board = ChessBoard::from_fen(fen).unwrap();
+    let mut castles: Vec<String> = board.legal_moves()
+        .iter()
+        .filter(|mv| board.kings.contains(mv.from) &&
+                     (mv.from.file() as i32 - mv.to.file() as i32).abs() == 2)
+        .map(|mv| mv.to_string())
+        .collect();
+    castles.sort();
+    castles
+}
+
+#[test]
+fn test_castling_out_of_check() {
+    assert!(castles("4k3/4r3/8/8/8/8/8/R3K2R w KQ - 0 1").is_empty());
+    assert_eq!(castles("r3k2r/8/8/8/8/8/8/4K1N1 b kq - 0 1"), ["E8C8", "E8G8"]);
+    assert!(castles("r3k2r/8/8/1B6/8/8/8/4K3 b kq - 0 1").is_empty());
+}
+
+#[test]
+fn test_castling_through_attacked_squares() {
+    /* The king may not cross or land on an attacked square... */
+    assert_eq!(castles("4k3/5r2/8/8/8/8/8/R3K2R w KQ - 0 1"), ["E1C1"]);
+    assert_eq!(castles("4k3/3r4/8/8/8/8/8/R3K2R w KQ - 0 1"), ["E1G1"]);
+    assert_eq!(castles("4k3/6r1/8/8/8/8/8/R3K2R w KQ - 0 1"), ["E1C1"]);
+    assert_eq!(castles("r3k2r/8/8/8/8/8/8/2R1K3 b kq - 0 1"), ["E8G8"]);
+    /* ...but the rook may, so b1 being attacked doesn't matter. */
+    assert_eq!(castles("4k3/1r6/8/8/8/8/8/R3K2R w KQ - 0 1"), ["E1C1", "E1G1"]);
+    /* Nor may anything stand in the way. */
+    assert_eq!(castles("4k3/8/8/8/8/8/8/RN2K2R w KQ - 0 1"), ["E1G1"]);
+}
+
+#[test]
+fn test_castling_rights_lost_by_rook_capture() {
+    let mut board = ChessBoard::from_fen("4k3/8/8/8/8/8/6b1/R3K2R b KQ - 0 1").unwrap();
+    play(&mut board, &["G2H1"]);
+    assert!(!board.white_castling.kingside);
+    assert!(board.white_castling.queenside);
+    assert_eq!(castles(&board.to_fen()), ["E1C1"]);
+
+    let mut board = ChessBoard::from_fen("r3k2r/8/8/8/8/8/8/R3K1N1 w Qkq - 0 1").unwrap();
+    play(&mut board, &["A1A8"]);
+    assert!(!board.black_castling.queenside);
+    assert!(board.black_castling.kingside);
+    assert!(!board.white_castling.queenside);
+}
+
+#[test]
+fn test_en_passant_ally() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4", "A7A6", "E4E5", "D7D5"]);
+
+    let capture = board.move_from_str("E5D6").unwrap();
+    assert!(board.gen_ordered_moves(Team::Ally).contains(&capture));
+    board.do_move(&capture);
+
+    assert!(board.pawns.intersect(board.white).contains(BitboardPiece::from_file_rank(3, 5)));
+    assert!(!board.pawns.contains(BitboardPiece::from_file_rank(3, 4)));
+    assert!(!board.black.contains(BitboardPiece::from_file_rank(3, 4)));
+    assert!(board.en_passant.is_empty());
+}
+
+#[test]
+fn test_en_passant_enemy() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["A2A3", "D7D5", "A3A4", "D5D4", "E2E4"]);
+
+    let capture = board.move_from_str("D4E3").unwrap();
+    assert!(board.gen_ordered_moves(Team::Enemy).contains(&capture));
+    board.do_move(&capture);
+
+    assert!(board.pawns.intersect(board.black).contains(BitboardPiece::from_file_rank(4, 2)));
+    assert!(!board.pawns.contains(BitboardPiece::from_file_rank(4, 3)));
+}
+
+#[test]
+fn test_en_passant_expires() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4", "A7A6", "E4E5", "D7D5", "H2H3", "H7H6"]);
+
+    let capture = board.move_from_str("E5D6").unwrap();
+    assert!(!board.gen_ordered_moves(Team::Ally).contains(&capture));
+}
+
+#[test]
+fn test_promotion() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["B2B4", "A7A5", "B4A5", "B7B6", "A5B6", "H7H6", "B6B7", "H6H5"]);
+
+    let moves = board.gen_ordered_moves(Team::Ally);
+    for s in ["B7A8Q", "B7A8R", "B7A8B", "B7A8N", "B7C8Q", "B7C8N"].iter() {
+        assert!(moves.contains(&board.move_from_str(s).unwrap()));
     }
+    assert!(!moves.contains(&board.move_from_str("B7A8").unwrap()));
 
-    fn gen_enemy_moves(&self) -> Vec<Self::Move> {
-        let mut moves = Vec::new();
-        let flipped = self.as_other();
+    let mv = board.move_from_str("B7A8N").unwrap();
+    board.do_move(&mv);
+    let a8 = BitboardPiece::from_file_rank(0, 7);
+    assert!(board.knights.intersect(board.white).contains(a8));
+    assert!(!board.pawns.contains(a8));
+    assert!(!board.rooks.contains(a8));
+}
 
-        moves.extend(flipped.gen_pawn_moves());
-        moves.extend(flipped.gen_king_moves());
-        moves.extend(flipped.gen_knight_moves());
-        moves.extend(flipped.gen_bishop_moves());
-        moves.extend(flipped.gen_queen_moves());
-        moves.extend(flipped.gen_rook_moves());
+#[test]
+fn test_move_str_promotion() {
+    let board = ChessBoard::new();
+    let mv = board.move_from_str("e7e8q").unwrap();
+    assert_eq!(mv.promotion, Some(Piece::Queen));
+    assert_eq!(format!("{}", mv), "E7E8Q");
+    assert!(board.move_from_str("E7E8K").is_err());
+    assert!(board.move_from_str("E7E8QQ").is_err());
+}
 
-        for mv in moves.iter_mut() {
-            mv.flip_vertical();
+/* Plays `games` pseudo-random games of up to `plies` moves from each of
+ * `fens`, returning where each one started and the moves played. The
+ * seed is fixed so every run plays the same games.
+ */
+#[cfg(test)]
+fn random_games(fens: &[&str], games: usize, plies: usize) -> Vec<(ChessBoard, Vec<ChessMove>)> {
+    let mut seed: u64 = 0x2545F4914F6CDD1D;
+    let mut played = Vec::new();
+    for fen in fens.iter() {
+        let start = ChessBoard::from_fen(fen).unwrap();
+        for _ in 0..games {
+            let mut board = start.clone();
+            let mut moves = Vec::new();
+            for _ in 0..plies {
+                let legal = board.legal_moves();
+                if legal.is_empty() {
+                    break;
+                }
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                let mv = legal[(seed % legal.len() as u64) as usize];
+                board.do_move(&mv);
+                moves.push(mv);
+            }
+            played.push((start.clone(), moves));
         }
+    }
+    played
+}
 
-        moves
+/* Calls `check` on every position of the games `random_games` plays. */
+#[cfg(test)]
+fn for_random_positions<F: FnMut(&ChessBoard)>(fens: &[&str], games: usize, plies: usize,
+                                               mut check: F) {
+    for (mut board, moves) in random_games(fens, games, plies) {
+        check(&board);
+        for mv in moves.iter() {
+            board.do_move(mv);
+            check(&board);
+        }
+    }
+}
+
+/* A sort key giving moves a fixed order, so lists from different
+ * generators can be compared.
+ */
+#[cfg(test)]
+fn move_key(mv: &ChessMove) -> (usize, usize, usize, usize, Option<char>) {
+    (mv.from.rank(), mv.from.file(), mv.to.rank(), mv.to.file(), mv.promotion.map(|p| p.to_char()))
+}
+
+/* Checks that the legal move generator agrees with playing out each
+ * pseudo-legal move and seeing whether the king is left attacked.
+ */
+#[test]
+fn test_legal_moves_match_make_and_test() {
+    let start = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"];
+    for_random_positions(&start, 40, 150, |board| {
+        let us = board.turn();
+        let king = board.king_square(us).unwrap();
+        let mut expected: Vec<ChessMove> = board.gen_pseudo_legal_moves(us, Stage::All)
+            .into_iter()
+            .filter(|mv| {
+                let mut after = board.clone();
+                after.do_move(mv);
+                let king = if mv.from == king { mv.to } else { king };
+                !after.is_attacked(king, us.other_team())
+            })
+            .collect();
+        let mut moves = board.gen_legal_moves(us);
+        expected.sort_by_key(move_key);
+        moves.sort_by_key(move_key);
+        assert_eq!(&moves[..], &expected[..]);
+    });
+}
+
+#[test]
+fn test_pinned_piece_stays_on_line() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4", "E7E5", "D2D4", "F8B4", "B1D2"]);
+
+    /* The knight on d2 is pinned by the bishop on b4. */
+    let moves = board.gen_ordered_moves(Team::Ally);
+    assert!(!moves.iter().any(|mv| mv.from == BitboardPiece::from_file_rank(3, 1)));
+    assert!(moves.contains(&board.move_from_str("C2C3").unwrap()));
+}
+
+#[test]
+fn test_check_evasions() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4", "E7E5", "D2D3", "F8B4"]);
+
+    let mut moves: Vec<String> = board.gen_ordered_moves(Team::Ally).iter().map(|mv| mv.to_string()).collect();
+    moves.sort();
+    assert_eq!(moves, ["B1C3", "B1D2", "C1D2", "C2C3", "D1D2", "E1E2"]);
+}
+
+#[test]
+fn test_outcome_checkmate() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["F2F3", "E7E5", "G2G4", "D8H4"]);
+
+    match board.outcome() {
+        GameOutcome::Checkmate(Team::Enemy) => {}
+        outcome => panic!("expected black to win, got {:?}", outcome),
+    }
+    assert!(!matches!(board.outcome(), GameOutcome::Ongoing));
+}
+
+#[test]
+fn test_outcome_stalemate() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E3", "A7A5", "D1H5", "A8A6", "H5A5", "H7H5", "H2H4", "A6H6",
+                       "A5C7", "F7F6", "C7D7", "E8F7", "D7B7", "D8D3", "B7B8", "D3H7",
+                       "B8C8", "F7G6", "C8E6"]);
+
+    match board.outcome() {
+        GameOutcome::Stalemate => {}
+        outcome => panic!("expected stalemate, got {:?}", outcome),
+    }
+}
+
+#[test]
+fn test_outcome_ongoing() {
+    let board = ChessBoard::new();
+    match board.outcome() {
+        GameOutcome::Ongoing => {}
+        outcome => panic!("expected ongoing game, got {:?}", outcome),
     }
+}
+
+#[test]
+fn test_threefold_repetition() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["G1F3", "G8F6", "F3G1", "F6G8", "G1F3", "G8F6", "F3G1"]);
+    assert_eq!(board.repetitions(), 2);
+    assert!(matches!(board.outcome(), GameOutcome::Ongoing));
+
+    play(&mut board, &["F6G8"]);
+    assert_eq!(board.repetitions(), 3);
+    match board.outcome() {
+        GameOutcome::Draw(DrawReason::ThreefoldRepetition) => {}
+        outcome => panic!("expected a repetition draw, got {:?}", outcome),
+    }
+}
+
+#[test]
+fn test_clone_keeps_history_for_undo() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["G1F3", "G8F6", "F3G1", "F6G8"]);
+    let undo = board.do_move(&board.move_from_str("E2E4").unwrap());
+
+    let mut clone = board.clone();
+    clone.undo_move(&undo);
+    assert_eq!(clone.repetitions(), 2);
+    assert_eq!(clone.history, board.history[..4]);
+}
+
+#[test]
+fn test_clone_for_search_drops_unrepeatable_history() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E3", "E7E6", "G1F3", "G8F6", "F3G1", "F6G8", "G1F3", "G8F6"]);
+    let clone = board.clone_for_search();
+    assert_eq!(board.history.len(), 8);
+    assert_eq!(clone.history.len(), 6);
+    assert_eq!(clone.repetitions(), board.repetitions());
+
+    let mut board = clone;
+    play(&mut board, &["F3G1", "F6G8"]);
+    assert!(!matches!(board.outcome(), GameOutcome::Ongoing));
+}
+
+#[test]
+fn test_fifty_move_rule() {
+    let mut board = ChessBoard::new();
+    board.halfmove_clock = 98;
+    play(&mut board, &["G1F3"]);
+    assert!(matches!(board.outcome(), GameOutcome::Ongoing));
+
+    play(&mut board, &["G8F6"]);
+    match board.outcome() {
+        GameOutcome::Draw(DrawReason::FiftyMoveRule) => {}
+        outcome => panic!("expected a fifty-move draw, got {:?}", outcome),
+    }
+
+    play(&mut board, &["E2E4"]);
+    assert_eq!(board.halfmove_clock, 0);
+}
+
+#[test]
+fn test_insufficient_material() {
+    let mut board = ChessBoard::new();
+    let empty = Bitboard::from_u64(0);
+    board.pawns = empty;
+    board.rooks = empty;
+    board.queens = empty;
+    board.knights = empty;
+    board.bishops = Bitboard::from_u64(0x2400000000000024);
+    board.white = board.kings.union(board.bishops).intersect(Bitboard::from_u64(0xFF));
+    board.black = board.kings.union(board.bishops).intersect(board.white.complement());
+
+    /* Two bishops each, on both colours. */
+    assert!(!board.is_insufficient_material());
+
+    board.bishops = Bitboard::from_u64(0x2000000000000004);
+    board.white = board.white.intersect(board.kings.union(board.bishops));
+    board.black = board.black.intersect(board.kings.union(board.bishops));
+    /* c1 and f8 are both dark squares. */
+    assert!(board.is_insufficient_material());
+
+    board.knights = Bitboard::from_u64(0x02);
+    board.white = board.white.union(board.knights);
+    assert!(!board.is_insufficient_material());
+}
+
+#[test]
+fn test_fen_start_position() {
+    let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    let board = ChessBoard::from_fen(start).unwrap();
+    assert_eq!(board, ChessBoard::new());
+    assert_eq!(ChessBoard::new().to_fen(), start);
+}
 
-    fn do_move(&mut self, mv: &Self::Move) {
-        for board in vec![&mut self.pawns,
-                          &mut self.rooks,
-                          &mut self.knights,
-                          &mut self.bishops,
-                          &mut self.queens,
-                          &mut self.kings,
-                          &mut self.enemies,
-                          &mut self.allies]
-            .into_iter() {
-            board.remove(mv.to);
-            if board.contains(mv.from) {
-                board.remove(mv.from);
-                board.add(mv.to);
+#[test]
+fn test_fen_after_moves() {
+    let mut board = ChessBoard::new();
+    play(&mut board, &["E2E4"]);
+    assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+
+    play(&mut board, &["C7C5", "G1F3"]);
+    assert_eq!(board.to_fen(), "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+}
+
+#[test]
+fn test_fen_round_trip() {
+    let fens = ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "4k3/8/8/8/8/8/8/4K2R b K - 37 120"];
+    for fen in fens.iter() {
+        assert_eq!(ChessBoard::from_fen(fen).unwrap().to_fen(), *fen);
+    }
+
+    let short = ChessBoard::from_fen("4k3/8/8/8/8/8/8/4K3 b - -").unwrap();
+    assert_eq!(short.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+}
+
+#[test]
+fn test_fen_errors() {
+    let cases = [("8/8/8/8/8/8/8/8 w - - 0", FenError::FieldCount(5)),
+                 ("4k3/8/8/8/8/8/4K3 w - - 0 1", FenError::RankCount(7)),
+                 ("4k3/8/8/8/8/8/8/4K4 w - - 0 1", FenError::RankLength(1)),
+                 ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::UnknownPiece('X')),
+                 ("4k2P/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank),
+                 ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::KingCount),
+                 ("QQQQQQQk/Q6Q/Q6Q/Q6Q/Q6Q/Q2Q1Q1Q/QQ5Q/K1Q1Q1Q1 w - - 0 1", FenError::PieceCount),
+                 ("4k3/pppppppp/p7/8/8/8/8/4K3 w - - 0 1", FenError::PieceCount),
+                 ("4k3/8/8/8/8/8/PPPPPPPP/QQ2K3 w - - 0 1", FenError::PieceCount),
+                 ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::SideToMove("x".to_string())),
+                 ("4k3/8/8/8/8/8/8/4K3 w KK - 0 1", FenError::Castling("KK".to_string())),
+                 ("4k3/8/8/8/8/8/8/4K3 w - e3 0 1", FenError::EnPassant("e3".to_string())),
+                 ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::HalfmoveClock("x".to_string())),
+                 ("4k3/8/8/8/8/8/8/4K3 w - - 0 0", FenError::FullmoveNumber("0".to_string()))];
+    for &(fen, ref error) in cases.iter() {
+        assert_eq!(ChessBoard::from_fen(fen).unwrap_err(), *error);
+    }
+}
+
+#[test]
+fn test_zobrist_key() {
+    /* Transpositions share a key, and it matches a board read from FEN. */
+    let mut a = ChessBoard::new();
+    play(&mut a, &["G1F3", "G8F6", "B1C3", "B8C6"]);
+    let mut b = ChessBoard::new();
+    play(&mut b, &["B1C3", "B8C6", "G1F3", "G8F6"]);
+    assert_eq!(a.zobrist_key(), b.zobrist_key());
+    assert_eq!(a.zobrist_key(), ChessBoard::from_fen(&a.to_fen()).unwrap().zobrist_key());
+
+    /* The team to move, castling rights and en passant all count. */
+    let keys: Vec<u64> = ["4k3/8/8/8/8/8/8/R3K3 w Q - 0 1",
+                          "4k3/8/8/8/8/8/8/R3K3 b Q - 0 1",
+                          "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+                          "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+                          "4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1"]
+        .iter()
+        .map(|fen| ChessBoard::from_fen(fen).unwrap().zobrist_key())
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        assert!(keys[i + 1..].iter().all(|other| other != key));
+    }
+
+    /* Castling, en passant and promotion all go through the debug check. */
+    let mut board = ChessBoard::from_fen("r3k2r/1P6/8/8/4p3/8/3P4/R3K2R w KQkq - 0 1").unwrap();
+    play(&mut board, &["D2D4", "E4D3", "E1C1", "E8G8", "B7A8Q"]);
+    assert_eq!(board.zobrist_key(), ChessBoard::from_fen(&board.to_fen()).unwrap().zobrist_key());
+}
+
+#[test]
+fn test_pawn_key() {
+    /* Only pawns count, whoever's turn it is. */
+    let mut board = ChessBoard::new();
+    let start = board.pawn_key();
+    play(&mut board, &["G1F3", "G8F6"]);
+    assert_eq!(board.pawn_key(), start);
+    play(&mut board, &["E2E4"]);
+    assert!(board.pawn_key() != start);
+    assert_eq!(board.pawn_key(), ChessBoard::from_fen(&board.to_fen()).unwrap().pawn_key());
+
+    /* Pieces taking pawns, en passant and promotion all change it. */
+    let mut board = ChessBoard::from_fen("r3k3/1P6/8/8/4p3/8/3P4/4K2R w K - 0 1").unwrap();
+    play(&mut board, &["D2D4", "E4D3", "E1G1", "E8E7", "B7A8Q", "E7E6", "F1F3", "E6E5", "F3D3"]);
+    assert_eq!(board.pawn_key(), 0);
+    assert_eq!(ChessBoard::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap().pawn_key(), 0);
+}
+
+#[test]
+fn test_undo_move_unwinds_random_games() {
+    let starts = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                  "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                  "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1"];
+    for (mut board, moves) in random_games(&starts, 20, 120) {
+        let mut line = Vec::new();
+        for mv in moves.iter() {
+            let before = board.clone();
+            let undo = board.do_move(mv);
+            line.push((before, undo));
+        }
+
+        while let Some((before, undo)) = line.pop() {
+            board.undo_move(&undo);
+            assert!(board == before, "undoing {} gave {}", undo.mv, board.to_fen());
+            assert_eq!(board.to_fen(), before.to_fen());
+            assert_eq!(board.zobrist, before.zobrist);
+            assert_eq!(board.pawn_zobrist, before.pawn_zobrist);
+            assert_eq!(board.history, before.history);
+        }
+    }
+}
+
+/* Flips the board top to bottom and swaps the teams over, which is how
+ * the enemy's moves used to be generated. Kept to check that generating
+ * for either team directly gives the same moves.
+ */
+#[cfg(test)]
+pub fn mirrored(board: &ChessBoard) -> ChessBoard {
+    let mut mirror = ChessBoard {
+        pawns: board.pawns.flip_vertical(),
+        rooks: board.rooks.flip_vertical(),
+        knights: board.knights.flip_vertical(),
+        bishops: board.bishops.flip_vertical(),
+        queens: board.queens.flip_vertical(),
+        kings: board.kings.flip_vertical(),
+        black: board.white.flip_vertical(),
+        white: board.black.flip_vertical(),
+        white_castling: board.black_castling,
+        black_castling: board.white_castling,
+        en_passant: board.en_passant.flip_vertical(),
+        white_to_move: !board.white_to_move,
+        halfmove_clock: board.halfmove_clock,
+        fullmove_number: board.fullmove_number,
+        history: Vec::new(),
+        zobrist: 0,
+        pawn_zobrist: 0,
+    };
+    mirror.zobrist = mirror.compute_zobrist();
+    mirror.pawn_zobrist = mirror.compute_pawn_zobrist();
+    mirror
+}
+
+#[test]
+fn test_moves_match_mirrored_position() {
+    let starts = ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                  "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                  "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"];
+    for_random_positions(&starts, 10, 100, |board| {
+        let mirror = mirrored(board);
+        for &team in [Team::Ally, Team::Enemy].iter() {
+            let mut moves = board.gen_legal_moves(team);
+            let mut expected = mirror.gen_legal_moves(team.other_team());
+            for mv in expected.iter_mut() {
+                mv.flip_vertical();
             }
+            moves.sort_by_key(move_key);
+            expected.sort_by_key(move_key);
+            assert_eq!(&moves[..], &expected[..], "{}", board.to_fen());
+        }
+    });
+}
+
+/* The square-by-square generators that the attack tables and pawn
+ * shifts replaced, kept to check the new ones against.
+ */
+#[cfg(test)]
+fn moves_by_offsets(board: &ChessBoard) -> Vec<ChessMove> {
+    use attacks::KNIGHT_OFFSETS;
+
+    let mut moves = Vec::new();
+    let mut add = |from: BitboardPiece, x: i32, y: i32, allowed: Bitboard| {
+        if let Some(to) = offset(from, x, y) {
+            if allowed.contains(to) {
+                let mv = ChessMove {
+                    from,
+                    to,
+                    promotion: None,
+                };
+                if board.pawns.contains(from) && to.rank() == 7 {
+                    moves.extend(PROMOTIONS.iter().map(|&piece| ChessMove {
+                        promotion: Some(piece),
+                        ..mv
+                    }));
+                } else {
+                    moves.push(mv);
+                }
+            }
+        }
+    };
+
+    let not_allies = board.white.complement();
+    for knight in board.knights.intersect(board.white).pieces() {
+        for &(x, y) in KNIGHT_OFFSETS.iter() {
+            add(knight, x, y, not_allies);
+        }
+    }
+    for king in board.kings.intersect(board.white).pieces() {
+        for &(x, y) in KING_OFFSETS.iter() {
+            add(king, x, y, not_allies);
         }
     }
 
-    fn score(&self) -> Score {
-        if self.kings.intersect(self.allies).is_empty() {
-            Score::Lose
-        } else if self.kings.intersect(self.enemies).is_empty() {
-            Score::Win
-        } else {
-            /* King score is taken into account with above */
-            let pawn_score = self.pawns.intersect(self.allies).num_pieces() as i32 -
-                             self.pawns.intersect(self.enemies).num_pieces() as i32;
-            let rook_score = self.rooks.intersect(self.allies).num_pieces() as i32 -
-                             self.rooks.intersect(self.enemies).num_pieces() as i32;
-            let knight_score = self.knights.intersect(self.allies).num_pieces() as i32 -
-                               self.knights.intersect(self.enemies).num_pieces() as i32;
-            let bishop_score = self.bishops.intersect(self.allies).num_pieces() as i32 -
-                               self.bishops.intersect(self.enemies).num_pieces() as i32;
-            let queen_score = self.queens.intersect(self.allies).num_pieces() as i32 -
-                              self.queens.intersect(self.enemies).num_pieces() as i32;
-            Score::Heuristic((pawn_score * 1) + (rook_score * 5) + (knight_score * 3) +
-                             (bishop_score * 3) + (queen_score * 9))
+    let empty = board.white.union(board.black).complement();
+    let targets = board.black
+        .union(board.en_passant.intersect(Bitboard::from_u64(attacks::RANK_6)));
+    for pawn in board.pawns.intersect(board.white).pieces() {
+        add(pawn, -1, 1, targets);
+        add(pawn, 1, 1, targets);
+        add(pawn, 0, 1, empty);
+        if pawn.rank() == 1 && empty.contains(pawn.shift(0, 1)) {
+            add(pawn, 0, 2, empty);
         }
     }
+    moves
+}
 
-    fn is_game_over(&self) -> bool {
-        self.kings.intersect(self.allies).is_empty() || self.kings.intersect(self.enemies).is_empty()
+#[test]
+fn test_table_move_generation_matches_offsets() {
+    let starts = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                  "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                  "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                  "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"];
+    for_random_positions(&starts, 10, 100, |board| {
+        let view = if board.white_to_move { board.clone() } else { mirrored(board) };
+        let targets = view.white.complement();
+        let mut moves = MoveList::new();
+        view.gen_pawn_moves(Team::Ally, !0, !0, &mut moves);
+        view.gen_piece_moves(Team::Ally, view.knights, attacks::knight_attacks, targets,
+                             &mut moves);
+        view.gen_piece_moves(Team::Ally, view.kings, attacks::king_attacks, targets, &mut moves);
+        view.gen_castling_moves(Team::Ally, &mut moves);
+        let mut expected = MoveList::new();
+        expected.extend(moves_by_offsets(&view));
+        view.gen_castling_moves(Team::Ally, &mut expected);
+        moves.sort_by_key(move_key);
+        expected.sort_by_key(move_key);
+        assert_eq!(&moves[..], &expected[..], "{}", board.to_fen());
+    });
+}
+
+#[cfg(test)]
+fn squares(names: &[&str]) -> Bitboard {
+    let mut board = Bitboard::from_u64(0);
+    for name in names {
+        board.add(parse_square(name).unwrap());
     }
+    board
+}
+
+#[test]
+fn test_attackers_to() {
+    let square = |name| parse_square(name).unwrap();
+    let board = ChessBoard::from_fen("4k3/8/8/3p4/4P3/2N5/8/R3K2R w KQ - 0 1").unwrap();
+    assert_eq!(board.attackers_to(square("d5"), Team::Ally), squares(&["e4", "c3"]));
+    assert_eq!(board.attackers_to(square("e4"), Team::Enemy), squares(&["d5"]));
+    assert_eq!(board.attackers_to(square("d1"), Team::Ally), squares(&["a1", "c3", "e1"]));
+    assert_eq!(board.attackers_to(square("d7"), Team::Enemy), squares(&["e8"]));
+    assert!(board.attackers_to(square("e8"), Team::Ally).is_empty());
+    assert_eq!(board.attackers_to(square("h8"), Team::Ally), squares(&["h1"]));
+
+    assert!(board.is_attacked(square("c4"), Team::Enemy));
+    assert!(!board.is_attacked(square("d4"), Team::Enemy));
+    assert!(board.is_attacked(square("b5"), Team::Ally));
+    assert!(!board.is_attacked(square("e5"), Team::Ally));
+}
+
+#[test]
+fn test_attackers_to_sliders_and_blockers() {
+    let square = |name| parse_square(name).unwrap();
+    let board = ChessBoard::from_fen("4k3/8/8/8/1b6/8/3P4/4K2R b K - 0 1").unwrap();
+    assert!(board.is_attacked(square("d2"), Team::Enemy));
+    assert!(!board.is_attacked(square("e1"), Team::Enemy));
+    assert_eq!(board.attackers_to(square("c3"), Team::Enemy), squares(&["b4"]));
+    assert_eq!(board.attackers_to(square("h8"), Team::Ally), squares(&["h1"]));
+    assert_eq!(board.attackers_to(square("f1"), Team::Ally), squares(&["e1", "h1"]));
+}
+
+#[test]
+fn test_in_check() {
+    let in_check = |fen| ChessBoard::from_fen(fen).unwrap().in_check();
+    assert!(in_check("4k3/8/8/8/1b6/8/8/4K3 w - - 0 1"));
+    assert!(!in_check("4k3/8/8/8/1b6/8/3P4/4K3 w - - 0 1"));
+    assert!(in_check("8/8/8/8/8/5k2/4P3/4K3 b - - 0 1"));
+    assert!(!in_check("8/8/8/8/8/4k3/4P3/4K3 b - - 0 1"));
+    assert!(!in_check("4k3/8/8/8/8/8/8/4KR1r w - - 0 1"));
+    assert!(in_check("4k3/8/8/8/8/8/8/4K2r w - - 0 1"));
+    assert!(!in_check("r3k3/8/8/8/8/8/8/N3K3 b q - 0 1"));
+    assert!(in_check("4k3/8/3N4/8/8/8/8/4K3 b - - 0 1"));
+}
+
+/* Checks each staged generator against the full generator filtered
+ * down to the same moves, for both teams, in a spread of positions and
+ * the random games played on from them.
+ */
+#[test]
+fn test_staged_moves_match_filtered_moves() {
+    let fens = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+                "8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 0 1",
+                "4k3/8/8/8/1b6/8/8/4K3 w - - 0 1",
+                "4k3/8/3N4/8/8/8/8/4K2R b K - 0 1",
+                "4k3/4r3/8/8/8/8/3PPP2/1r2K3 w - - 0 1",
+                "3r2k1/1P3ppp/8/8/8/8/5PPP/6K1 w - - 0 1"];
+    for_random_positions(&fens, 1, 40, |board| {
+        for &us in [Team::Ally, Team::Enemy].iter() {
+            let all = board.gen_legal_moves(us);
+            let is_tactical = |mv: &&ChessMove| {
+                mv.promotion.is_some() || board.side(us.other_team()).contains(mv.to) ||
+                (board.pawns.contains(mv.from) && mv.from.file() != mv.to.file())
+            };
+            let check = |moves: MoveList, expected: Vec<ChessMove>| {
+                let mut moves = moves.to_vec();
+                let mut expected = expected;
+                moves.sort_by_key(move_key);
+                expected.sort_by_key(move_key);
+                assert_eq!(moves, expected, "{}", board.to_fen());
+            };
+
+            check(board.tactical_moves(us), all.iter().filter(is_tactical).cloned().collect());
+            check(board.quiet_moves(us),
+                  all.iter().filter(|mv| !is_tactical(mv)).cloned().collect());
+            let evasions = if board.king_in_check(us) { all.to_vec() } else { Vec::new() };
+            check(board.evasions(us), evasions);
+        }
+    });
+}
+
+#[test]
+fn test_see() {
+    let see = |fen, mv| {
+        let board = ChessBoard::from_fen(fen).unwrap();
+        let mv = board.move_from_str(mv).unwrap();
+        board.see(&mv)
+    };
+    /* An undefended pawn. */
+    assert_eq!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "E1E5"), 100);
+    /* A pawn for a knight, with queens x-raying through a rook and a
+     * bishop on both sides.
+     */
+    assert_eq!(see("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "D3E5"), -220);
+    /* Pawn takes a defended knight. */
+    assert_eq!(see("4k3/8/3p4/4n3/3P4/8/8/4K3 w - - 0 1", "D4E5"), 220);
+    /* Queen takes a defended pawn. */
+    assert_eq!(see("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1", "D1D5"), -800);
+    /* A quiet move onto an attacked square just loses the piece. */
+    assert_eq!(see("4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1", "D1D5"), -900);
+    /* The king takes back unless the rook is defended. */
+    assert_eq!(see("8/8/2k5/3p4/8/8/8/3RK3 w - - 0 1", "D1D5"), -400);
+    assert_eq!(see("8/8/2k5/3p4/8/1B6/8/3RK3 w - - 0 1", "D1D5"), 100);
+    /* En passant and promotion. */
+    assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "E5D6"), 100);
+    assert_eq!(see("3r2k1/2P5/8/8/8/8/8/4K3 w - - 0 1", "C7D8Q"), 1300);
+    assert_eq!(see("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "C7D8Q"), 400);
+    assert_eq!(see("1r2k3/2P5/8/8/8/8/8/4K3 w - - 0 1", "C7C8Q"), -100);
 }
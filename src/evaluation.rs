@@ -0,0 +1,361 @@
+use chess_board::{ChessBoard, Piece};
+use pawns::{self, PawnTable};
+
+use minimax::Team;
+
+use std::ops::{Add, Sub};
+
+/* Every term of the evaluation is scored twice, once for the midgame and
+ * once for the endgame, and the two are blended by the game phase: how
+ * much material besides pawns is left on the board.
+ */
+
+/// The game phase with every piece still on the board.
+pub const MAX_PHASE: i32 = 24;
+
+/// A score kept as separate midgame and endgame parts.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Tapered {
+    pub midgame: i32,
+    pub endgame: i32,
+}
+
+impl Tapered {
+    pub fn new(midgame: i32, endgame: i32) -> Tapered {
+        Tapered {
+            midgame,
+            endgame,
+        }
+    }
+
+    /// Blends the two parts, leaning on the midgame part the closer
+    /// `phase` is to `MAX_PHASE`.
+    pub fn blend(self, phase: i32) -> i32 {
+        (self.midgame * phase + self.endgame * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Tapered {
+    type Output = Tapered;
+
+    fn add(self, other: Tapered) -> Tapered {
+        Tapered::new(self.midgame + other.midgame, self.endgame + other.endgame)
+    }
+}
+
+impl Sub for Tapered {
+    type Output = Tapered;
+
+    fn sub(self, other: Tapered) -> Tapered {
+        Tapered::new(self.midgame - other.midgame, self.endgame - other.endgame)
+    }
+}
+
+/* Piece-square tables give each piece a bonus or penalty, in centipawns,
+ * for the square it stands on. They're laid out the way White sees the
+ * board, with rank 8 at the top. Black's pieces are flipped vertically
+ * first, so both teams read the tables from their own side.
+ */
+
+const PAWN_MIDGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     10,  10,  20,  30,  30,  20,  10,  10,
+      5,   5,  10,  25,  25,  10,   5,   5,
+      0,   0,   0,  20,  20,   0,   0,   0,
+      5,  -5, -10,   0,   0, -10,  -5,   5,
+      5,  10,  10, -20, -20,  10,  10,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+const KNIGHT_MIDGAME: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+const BISHOP_MIDGAME: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+const ROOK_MIDGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+const QUEEN_MIDGAME: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+/* Once the board empties out, development counts for nothing and the
+ * pieces just want to be central...
+ */
+const KNIGHT_ENDGAME: [i32; 64] = [
+    -50, -40, -30, -25, -25, -30, -40, -50,
+    -40, -20,  -5,   0,   0,  -5, -20, -40,
+    -30,  -5,  10,  15,  15,  10,  -5, -30,
+    -25,   0,  15,  20,  20,  15,   0, -25,
+    -25,   0,  15,  20,  20,  15,   0, -25,
+    -30,  -5,  10,  15,  15,  10,  -5, -30,
+    -40, -20,  -5,   0,   0,  -5, -20, -40,
+    -50, -40, -30, -25, -25, -30, -40, -50,
+];
+
+const BISHOP_ENDGAME: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+const QUEEN_ENDGAME: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -10,   5,  10,  10,  10,  10,   5, -10,
+     -5,   5,  10,  15,  15,  10,   5,  -5,
+     -5,   5,  10,  15,  15,  10,   5,  -5,
+    -10,   5,  10,  10,  10,  10,   5, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+/* ...except rooks, which do their work on the seventh rank or behind
+ * pawns rather than in the middle.
+ */
+const ROOK_ENDGAME: [i32; 64] = [
+      5,   5,   5,   5,   5,   5,   5,   5,
+     20,  20,  20,  20,  20,  20,  20,  20,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+     -5,  -5,   0,   0,   0,   0,  -5,  -5,
+];
+
+/* Pawns race for promotion once the pieces come off. */
+const PAWN_ENDGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     80,  80,  80,  80,  80,  80,  80,  80,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     30,  30,  30,  30,  30,  30,  30,  30,
+     15,  15,  15,  15,  15,  15,  15,  15,
+      5,   5,   5,   5,   5,   5,   5,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+/* Kings stay tucked away behind their pawns in the midgame... */
+const KING_MIDGAME: [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+/* ...and come out to the middle for the endgame. */
+const KING_ENDGAME: [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+const PIECES: [Piece; 6] = [Piece::Pawn, Piece::Knight, Piece::Bishop,
+                            Piece::Rook, Piece::Queen, Piece::King];
+
+/// The midgame and endgame tables for `piece`.
+fn tables(piece: Piece) -> (&'static [i32; 64], &'static [i32; 64]) {
+    match piece {
+        Piece::Pawn => (&PAWN_MIDGAME, &PAWN_ENDGAME),
+        Piece::Knight => (&KNIGHT_MIDGAME, &KNIGHT_ENDGAME),
+        Piece::Bishop => (&BISHOP_MIDGAME, &BISHOP_ENDGAME),
+        Piece::Rook => (&ROOK_MIDGAME, &ROOK_ENDGAME),
+        Piece::Queen => (&QUEEN_MIDGAME, &QUEEN_ENDGAME),
+        Piece::King => (&KING_MIDGAME, &KING_ENDGAME),
+    }
+}
+
+/// What `piece` is worth in each phase: its usual value in the midgame,
+/// give or take a little in the endgame, where pawns, rooks and queens
+/// gain and the minor pieces lose.
+fn material(piece: Piece) -> Tapered {
+    let endgame_shift = match piece {
+        Piece::Pawn => 20,
+        Piece::Knight => -20,
+        Piece::Bishop => -10,
+        Piece::Rook => 30,
+        Piece::Queen => 50,
+        Piece::King => 0,
+    };
+    Tapered::new(piece.value(), piece.value() + endgame_shift)
+}
+
+/// How much `piece` counts toward the game phase.
+fn phase_weight(piece: Piece) -> i32 {
+    match piece {
+        Piece::Knight | Piece::Bishop => 1,
+        Piece::Rook => 2,
+        Piece::Queen => 4,
+        Piece::Pawn | Piece::King => 0,
+    }
+}
+
+/// Works out the game phase, from `MAX_PHASE` with every knight, bishop,
+/// rook and queen still on the board down to 0 with none of them left.
+/// Promotions can add material, so it never goes above `MAX_PHASE`.
+pub fn phase(board: &ChessBoard) -> i32 {
+    let phase: i32 = PIECES.iter()
+        .map(|&piece| {
+            let count = board.pieces_of(Team::Ally, piece)
+                .union(board.pieces_of(Team::Enemy, piece))
+                .num_pieces() as i32;
+            count * phase_weight(piece)
+        })
+        .sum();
+    phase.min(MAX_PHASE)
+}
+
+/// Scores the position in centipawns from White's side, without looking
+/// at any moves, keeping the midgame and endgame scores apart.
+pub fn evaluate_tapered(board: &ChessBoard, pawns: &mut PawnTable) -> Tapered {
+    side_score(board, Team::Ally) - side_score(board, Team::Enemy) + pawns::evaluate(board, pawns)
+}
+
+/// Scores the position in centipawns from White's side, without looking
+/// at any moves: the material on the board, where it stands and the pawn
+/// structure, blended by the game phase.
+pub fn evaluate(board: &ChessBoard, pawns: &mut PawnTable) -> i32 {
+    evaluate_tapered(board, pawns).blend(phase(board))
+}
+
+/// The material and placement of `team`'s pieces, as seen from its own
+/// side of the board.
+fn side_score(board: &ChessBoard, team: Team) -> Tapered {
+    let mut score = Tapered::default();
+    for &piece in PIECES.iter() {
+        let pieces = match team {
+            Team::Ally => board.pieces_of(team, piece),
+            Team::Enemy => board.pieces_of(team, piece).flip_vertical(),
+        };
+        let (midgame, endgame) = tables(piece);
+        for square in pieces.pieces() {
+            let index = (7 - square.rank()) * 8 + square.file();
+            score = score + material(piece) + Tapered::new(midgame[index], endgame[index]);
+        }
+    }
+    score
+}
+
+#[test]
+fn test_evaluation_is_symmetric() {
+    let fens = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"];
+    let mut pawns = PawnTable::new();
+    for fen in fens.iter() {
+        let board = ChessBoard::from_fen(fen).unwrap();
+        let mirror = ::chess_board::mirrored(&board);
+        assert_eq!(evaluate_tapered(&board, &mut pawns),
+                   Tapered::default() - evaluate_tapered(&mirror, &mut pawns), "{}", fen);
+        assert_eq!(evaluate(&board, &mut pawns), -evaluate(&mirror, &mut pawns), "{}", fen);
+    }
+    assert_eq!(evaluate(&ChessBoard::new(), &mut pawns), 0);
+}
+
+#[test]
+fn test_piece_placement() {
+    let mut pawns = PawnTable::new();
+    let mut score = |fen| evaluate(&ChessBoard::from_fen(fen).unwrap(), &mut pawns);
+    /* A centralised knight beats one on the rim. */
+    assert!(score("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1") > score("4k3/8/8/8/N7/8/8/4K3 w - - 0 1"));
+    /* An advanced pawn beats one at home, for either team. */
+    assert!(score("4k3/8/4P3/8/8/8/8/4K3 w - - 0 1") > score("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+    assert!(score("4k3/8/8/8/8/4p3/8/4K3 w - - 0 1") < score("4k3/4p3/8/8/8/8/8/4K3 w - - 0 1"));
+    /* Material still comes first. */
+    assert!(score("4k3/8/8/8/8/8/8/Q3K3 w - - 0 1") -
+            score("4k3/8/8/8/8/8/8/4K3 w - - 0 1") > 850);
+}
+
+#[test]
+fn test_game_phase() {
+    let board = |fen| ChessBoard::from_fen(fen).unwrap();
+    assert_eq!(phase(&ChessBoard::new()), MAX_PHASE);
+    assert_eq!(phase(&board("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1")), 0);
+    assert_eq!(phase(&board("r3k3/8/8/8/8/8/8/1N2KQ2 w - - 0 1")), 7);
+    assert_eq!(phase(&board("qqqqkqqq/8/8/8/8/8/8/QQQQKQQQ w - - 0 1")), MAX_PHASE);
+
+    assert_eq!(Tapered::new(100, 20).blend(MAX_PHASE), 100);
+    assert_eq!(Tapered::new(100, 20).blend(0), 20);
+    assert_eq!(Tapered::new(100, 20).blend(MAX_PHASE / 2), 60);
+}
+
+#[test]
+fn test_king_placement_by_phase() {
+    let mut pawns = PawnTable::new();
+    let mut tapered = |fen| evaluate_tapered(&ChessBoard::from_fen(fen).unwrap(), &mut pawns);
+    let castled = tapered("4k3/8/8/8/8/8/8/6K1 w - - 0 1");
+    let central = tapered("4k3/8/8/8/4K3/8/8/8 w - - 0 1");
+    /* A castled king is safer while there are pieces about, but a king in
+     * the middle is stronger once they're gone.
+     */
+    assert!(castled.midgame > central.midgame);
+    assert!(castled.endgame < central.endgame);
+    assert!(evaluate(&ChessBoard::from_fen("4k3/8/8/8/4K3/8/8/8 w - - 0 1").unwrap(), &mut pawns) >
+            evaluate(&ChessBoard::from_fen("4k3/8/8/8/8/8/8/6K1 w - - 0 1").unwrap(), &mut pawns));
+}
+
+#[test]
+fn test_material_and_piece_tables_by_phase() {
+    for &piece in PIECES.iter() {
+        assert_eq!(material(piece).midgame, piece.value());
+    }
+
+    let mut pawns = PawnTable::new();
+    let mut tapered = |fen| evaluate_tapered(&ChessBoard::from_fen(fen).unwrap(), &mut pawns);
+    let seventh = tapered("4k3/R7/8/8/8/8/8/4K3 w - - 0 1");
+    let fourth = tapered("4k3/8/8/8/R7/8/8/4K3 w - - 0 1");
+    /* A rook on the seventh matters more once the pieces are gone. */
+    assert!(seventh.endgame - fourth.endgame > seventh.midgame - fourth.midgame);
+    /* A queen at home has nothing to do in the endgame. */
+    let home = tapered("4k3/8/8/8/8/8/8/3QK3 w - - 0 1");
+    let central = tapered("4k3/8/8/8/3Q4/8/8/4K3 w - - 0 1");
+    assert!(central.endgame - home.endgame > central.midgame - home.midgame);
+}
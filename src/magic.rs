@@ -0,0 +1,203 @@
+use attacks::{bits, index};
+
+use bitboard::{Bitboard, BitboardPiece};
+
+use std::sync::OnceLock;
+
+/* Magic bitboards look up a slider's attacks in a table instead of
+ * walking its rays. The pieces that can block a slider on a square are
+ * the ones on its rays, minus the board edges. Multiplying those blockers
+ * by the square's magic number packs them into the top bits of the
+ * product, which index that square's slice of the table.
+ */
+
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, -1), (1, -1), (-1, 1)];
+
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: u64) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct Table {
+    magics: Vec<Magic>,
+    attacks: Vec<u64>,
+}
+
+impl Table {
+    /// Finds a magic number for every square by trial and error, then
+    /// fills in the attacks for every arrangement of blockers.
+    fn new(directions: &[(i32, i32)]) -> Table {
+        let mut table = Table {
+            magics: Vec::with_capacity(64),
+            attacks: Vec::new(),
+        };
+        /* A fixed seed makes startup take the same time on every run. */
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for square in 0..64 {
+            let mask = blocker_mask(square, directions);
+            let relevant = mask.count_ones();
+
+            /* Walk every subset of the mask with the Carry-Rippler trick. */
+            let mut blockers = Vec::with_capacity(1 << relevant);
+            let mut subset: u64 = 0;
+            loop {
+                blockers.push((subset, slide(square, subset, directions)));
+                subset = subset.wrapping_sub(mask) & mask;
+                if subset == 0 {
+                    break;
+                }
+            }
+
+            let offset = table.attacks.len();
+            table.attacks.resize(offset + (1 << relevant), 0);
+            let mut used = vec![false; 1 << relevant];
+            loop {
+                /* Sparse numbers make good magics, so and a few together. */
+                let magic = random() & random() & random();
+                let entry = Magic {
+                    mask,
+                    magic,
+                    shift: 64 - relevant,
+                    offset,
+                };
+                for flag in used.iter_mut() {
+                    *flag = false;
+                }
+
+                /* Different blockers may share a slot only if they
+                 * give the same attacks.
+                 */
+                let fits = blockers.iter().all(|&(occupied, attacks)| {
+                    let index = entry.index(occupied);
+                    let slot = index - offset;
+                    if used[slot] && table.attacks[index] != attacks {
+                        return false;
+                    }
+                    used[slot] = true;
+                    table.attacks[index] = attacks;
+                    true
+                });
+                if fits {
+                    table.magics.push(entry);
+                    break;
+                }
+            }
+        }
+
+        table
+    }
+
+    fn attacks(&self, square: BitboardPiece, occupied: Bitboard) -> Bitboard {
+        let magic = &self.magics[index(square)];
+        Bitboard::from_u64(self.attacks[magic.index(bits(occupied))])
+    }
+}
+
+/// Walks out from `square` in each direction, stopping at the first
+/// occupied square, which is included.
+fn slide(square: usize, occupied: u64, directions: &[(i32, i32)]) -> u64 {
+    let mut attacks = 0;
+    for &(x, y) in directions {
+        let mut file = (square % 8) as i32 + x;
+        let mut rank = (square / 8) as i32 + y;
+        while (0..8).contains(&file) && (0..8).contains(&rank) {
+            let bit = 1 << (rank * 8 + file);
+            attacks |= bit;
+            if occupied & bit != 0 {
+                break;
+            }
+            file += x;
+            rank += y;
+        }
+    }
+    attacks
+}
+
+/// The squares whose occupants could block a slider on `square`. The
+/// last square of each ray never matters, since it's attacked either way.
+fn blocker_mask(square: usize, directions: &[(i32, i32)]) -> u64 {
+    let mut mask = 0;
+    for &(x, y) in directions {
+        let mut file = (square % 8) as i32 + x;
+        let mut rank = (square / 8) as i32 + y;
+        while (0..8).contains(&(file + x)) && (0..8).contains(&(rank + y)) {
+            mask |= 1 << (rank * 8 + file);
+            file += x;
+            rank += y;
+        }
+    }
+    mask
+}
+
+fn rook_table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| Table::new(&ROOK_DIRECTIONS))
+}
+
+fn bishop_table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| Table::new(&BISHOP_DIRECTIONS))
+}
+
+/// Builds the attack tables now rather than on the first lookup, so the
+/// cost doesn't land in the middle of a timed search.
+pub fn init() {
+    rook_table();
+    bishop_table();
+}
+
+/// The squares a rook on `square` attacks, up to and including the
+/// first piece in `occupied` along each line.
+pub fn rook_attacks(square: BitboardPiece, occupied: Bitboard) -> Bitboard {
+    rook_table().attacks(square, occupied)
+}
+
+/// The squares a bishop on `square` attacks, up to and including the
+/// first piece in `occupied` along each diagonal.
+pub fn bishop_attacks(square: BitboardPiece, occupied: Bitboard) -> Bitboard {
+    bishop_table().attacks(square, occupied)
+}
+
+/// Both of the above, for queens.
+pub fn queen_attacks(square: BitboardPiece, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied).union(bishop_attacks(square, occupied))
+}
+
+#[test]
+fn test_magic_matches_rays() {
+    let mut seed: u64 = 0x9E3779B97F4A7C15;
+    for _ in 0..200 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let occupied = Bitboard::from_u64(seed & (seed >> 3));
+        for square in (0..64).map(|i| BitboardPiece::from_file_rank(i % 8, i / 8)) {
+            let mut origin = Bitboard::from_u64(0);
+            origin.add(square);
+            let rays = occupied.horizontal_ray(square)
+                .union(occupied.vertical_ray(square))
+                .intersect(origin.complement());
+            assert_eq!(rook_attacks(square, occupied), rays);
+            let rays = occupied.diagonal_ray(square)
+                .union(occupied.anti_diagonal_ray(square))
+                .intersect(origin.complement());
+            assert_eq!(bishop_attacks(square, occupied), rays);
+        }
+    }
+}
@@ -1,46 +1,182 @@
 extern crate minimax;
 extern crate bitboard;
 
+mod attacks;
 mod color;
+mod evaluation;
+mod magic;
+mod move_list;
 mod chess_board;
+mod san;
+mod pawns;
+mod pgn;
+mod perft;
+mod quiescence;
+mod search;
+mod uci;
+mod xboard;
+mod zobrist;
 
-use chess_board::ChessBoard;
+use chess_board::{ChessBoard, GameOutcome};
+use pawns::PawnTable;
+use pgn::{PgnGame, PgnResult};
+use search::SearchBoard;
 
-use minimax::board::Board;
-use minimax::{Minimax, Score, Team};
+use minimax::{Minimax, Team};
 
+use std::env;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
 use std::num::NonZeroUsize;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    magic::init();
+    match args.get(1).map(|arg| &arg[..]) {
+        Some("perft") => run_perft(&args[2..]),
+        Some("eval") => run_eval(&args[2..]),
+        Some("uci") => uci::run(),
+        Some("xboard") => xboard::run(),
+        _ => play(args.get(1).cloned()),
+    }
+}
+
+/// Runs `perft <depth> [fen]`, printing the node count under each move
+/// along with the total and how long it took.
+fn run_perft(args: &[String]) {
+    let depth = match args.first().and_then(|d| d.parse().ok()) {
+        Some(depth) => depth,
+        None => {
+            println!("Usage: perft <depth> [fen]");
+            process::exit(1);
+        }
+    };
+    let board = board_from_fen(&args[1..]);
+
+    let start = Instant::now();
+    let divide = perft::perft_divide(&board, depth);
+    let elapsed = start.elapsed();
+
+    let mut nodes = 0;
+    for &(mv, count) in divide.iter() {
+        println!("{}: {}", mv, count);
+        nodes += count;
+    }
+    /* There's nothing to divide at depth 0, but the position still counts. */
+    if depth == 0 {
+        nodes = perft::perft(&board, 0);
+    }
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    println!();
+    println!("Nodes: {}", nodes);
+    println!("Time: {:.3}s", seconds);
+    if seconds > 0.0 {
+        println!("Nodes/second: {:.0}", nodes as f64 / seconds);
+    }
+}
+
+/// Runs `eval [fen]`, printing the midgame and endgame scores for the
+/// position along with the game phase that blends them.
+fn run_eval(args: &[String]) {
+    let board = board_from_fen(args);
+    let phase = evaluation::phase(&board);
+    let tapered = evaluation::evaluate_tapered(&board, &mut PawnTable::new());
+    println!("Midgame: {}", tapered.midgame);
+    println!("Endgame: {}", tapered.endgame);
+    println!("Phase: {}/{}", phase, evaluation::MAX_PHASE);
+    println!("Score: {}", tapered.blend(phase));
+}
+
+/// Builds a board from a FEN given on the command line, or the starting
+/// position if there isn't one.
+fn board_from_fen(args: &[String]) -> ChessBoard {
+    if args.is_empty() {
+        return ChessBoard::new();
+    }
+    /* Let the FEN be passed either quoted or as separate words. */
+    match ChessBoard::from_fen(&args.join(" ")) {
+        Ok(board) => board,
+        Err(e) => {
+            println!("Bad FEN: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Plays an interactive game against the engine. The optional setup
+/// argument is either a FEN, or a PGN file whose last game we pick up.
+fn play(setup: Option<String>) {
     println!("Sorry, I'm a bit rusty at this game. Forgive me.");
 
-    let mut minimax = Minimax::new(NonZeroUsize::new(1000000).unwrap());
-    let mut board = ChessBoard::new();
-    let mut turn = Team::Ally;
+    let mut minimax: Minimax<SearchBoard> = Minimax::new(NonZeroUsize::new(1000000).unwrap());
+    let pawns = Arc::new(Mutex::new(PawnTable::new()));
+    let mut game = match setup {
+        Some(ref path) if path.ends_with(".pgn") => {
+            let games = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| pgn::parse_pgn(&text).map_err(|e| e.to_string()));
+            match games.map(|mut games| games.pop()) {
+                Ok(Some(game)) => game,
+                Ok(None) => {
+                    println!("No games in {}", path);
+                    process::exit(1);
+                }
+                Err(e) => {
+                    println!("Bad PGN: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Some(fen) => match ChessBoard::from_fen(&fen) {
+            Ok(board) => PgnGame::new(board),
+            Err(e) => {
+                println!("Bad FEN: {}", e);
+                process::exit(1);
+            }
+        },
+        None => PgnGame::new(ChessBoard::new()),
+    };
+    let mut board = game.board_at(game.moves.len());
+    /* Moves played this session, so the human can take theirs back. */
+    let mut undos = Vec::new();
+    game.result = PgnResult::Unknown;
+    game.set_tag("Event", "Rusty Chess AI game");
+    game.set_tag("White", "Human");
+    game.set_tag("Black", "rusty_chess_ai");
 
-    loop {
+    'game: loop {
         println!("\nCurrent board state:");
         board.print();
+        println!("FEN: {}", board.to_fen());
+        println!("Key: {:016x}", board.zobrist_key());
 
-        if board.is_game_over() {
-            let team = match board.score() {
-                Score::Win => Team::Ally,
-                Score::Lose => Team::Enemy,
-                _ => unreachable!(),
-            };
-            println!("{:?} wins!", team);
-            break;
+        match board.outcome() {
+            GameOutcome::Checkmate(team) => {
+                println!("Checkmate, {:?} wins!", team);
+                game.result = match team {
+                    Team::Ally => PgnResult::WhiteWins,
+                    Team::Enemy => PgnResult::BlackWins,
+                };
+                break;
+            }
+            GameOutcome::Stalemate => {
+                println!("Stalemate, it's a draw!");
+                game.result = PgnResult::Draw;
+                break;
+            }
+            GameOutcome::Draw(reason) => {
+                println!("Draw by {}!", reason);
+                game.result = PgnResult::Draw;
+                break;
+            }
+            GameOutcome::Ongoing => {}
         }
 
-        let moves = match turn {
-            Team::Ally => board.gen_ally_moves(),
-            Team::Enemy => board.gen_enemy_moves(),
-        };
-        if moves.len() == 0 {
-            println!("No more moves, {:?} wins!", turn);
-            break;
-        }
+        let turn = board.turn();
+        let moves = board.gen_ordered_moves(turn);
 
         match turn {
             Team::Ally => {
@@ -54,40 +190,71 @@ fn main() {
                         loop {}
                     }
                     move_str.truncate(move_str_len - 1);
-                    match board.move_from_str(&move_str) {
+                    if move_str == "undo" {
+                        /* Take back the engine's reply along with our move. */
+                        if undos.len() < 2 {
+                            println!("Nothing to take back");
+                            continue;
+                        }
+                        for undo in undos.drain(undos.len() - 2..).rev() {
+                            board.undo_move(&undo);
+                            game.moves.pop();
+                        }
+                        continue 'game;
+                    }
+                    /* Plain coordinates like "e2e4" first, since some of
+                     * them also read as SAN pawn moves. Then SAN.
+                     */
+                    let coordinates = board.move_from_str(&move_str)
+                        .ok()
+                        .filter(|mv| moves.contains(mv));
+                    match coordinates.map_or_else(|| board.move_from_san(&move_str), Ok) {
                         Ok(mv) => {
-                            if moves.contains(&mv) {
-                                board.do_move(&mv);
-                                break;
-                            } else {
-                                println!("Not a valid move");
-                            }
+                            undos.push(board.do_move(&mv));
+                            game.moves.push(mv);
+                            break;
                         }
-                        Err(_) => {
-                            println!("Bad move format");
+                        Err(e) => {
+                            println!("Not a valid move: {}", e);
                         }
                     }
                 }
             }
             Team::Enemy => {
                 println!("Computing best move...");
-                let move_stats = minimax.minimax(&board, turn, 6);
+                let search_board = SearchBoard::new(board.clone(), pawns.clone());
+                let move_stats = minimax.minimax(&search_board, turn, 6);
 
                 let best_move = move_stats.mv.unwrap();
                 println!();
                 println!("Score: {:?}", move_stats.score.score);
                 println!("Turns: {}", move_stats.score.turns);
                 println!("Nodes: {}", move_stats.nodes_visited);
-                println!("My move is: {}", best_move);
+                println!("Quiescence nodes: {}", search_board.quiescence_nodes());
+                println!("My move is: {}", board.move_to_san(&best_move));
                 print!("I expect: ");
+                let mut line = board.clone();
+                line.do_move(&best_move);
                 for mv in move_stats.mvs.iter().rev().skip(1) {
-                    print!("{} ", mv);
+                    print!("{} ", line.move_to_san(mv));
+                    line.do_move(mv);
                 }
                 println!();
-                board.do_move(&best_move);
+                undos.push(board.do_move(&best_move));
+                game.moves.push(best_move);
             }
         }
+    }
 
-        turn = turn.other_team();
+    let pgn = game.to_pgn();
+    println!("\n{}", pgn);
+    let saved = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open("games.pgn")
+        .and_then(|mut file| writeln!(file, "{}", pgn));
+    match saved {
+        Ok(()) => println!("Game saved to games.pgn"),
+        Err(e) => println!("Couldn't save the game: {}", e),
     }
 }
@@ -0,0 +1,175 @@
+use chess_board::ChessMove;
+
+use bitboard::BitboardPiece;
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// No legal chess position has more moves than this (the record is 218).
+pub const MAX_MOVES: usize = 256;
+
+/// A list of moves that lives on the stack, so generating moves doesn't
+/// touch the allocator. It derefs to a slice of the moves pushed so far.
+/// At 256 moves it's too big to copy without meaning to, so it isn't Copy.
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [ChessMove; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        /* Unused slots just need something in them. */
+        let corner = BitboardPiece::from_file_rank(0, 0);
+        MoveList {
+            moves: [ChessMove::new(corner, corner, None); MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, mv: ChessMove) {
+        debug_assert!(self.len < MAX_MOVES, "more than {} moves", MAX_MOVES);
+        self.moves[self.len] = mv;
+        self.len += 1;
+    }
+
+    /// Keeps only the moves `keep` returns true for, in their original
+    /// order.
+    pub fn retain<F: FnMut(&ChessMove) -> bool>(&mut self, mut keep: F) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            let mv = self.moves[i];
+            if keep(&mv) {
+                self.moves[kept] = mv;
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    /// Sorts the moves best first by `score`, which is worked out just
+    /// once per move, and returns how many of them scored zero or more.
+    pub fn sort_by_score<F: FnMut(&ChessMove) -> i32>(&mut self, mut score: F) -> usize {
+        let mut scores = [0; MAX_MOVES];
+        for (slot, mv) in scores.iter_mut().zip(self.iter()) {
+            *slot = score(mv);
+        }
+        /* An insertion sort, since the lists that get sorted are short.
+         * Moves that tie keep the order they were generated in.
+         */
+        for i in 1..self.len {
+            let (mv, key) = (self.moves[i], scores[i]);
+            let mut j = i;
+            while j > 0 && scores[j - 1] < key {
+                self.moves[j] = self.moves[j - 1];
+                scores[j] = scores[j - 1];
+                j -= 1;
+            }
+            self.moves[j] = mv;
+            scores[j] = key;
+        }
+        scores[..self.len].iter().take_while(|&&key| key >= 0).count()
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> MoveList {
+        MoveList::new()
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [ChessMove];
+
+    fn deref(&self) -> &[ChessMove] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [ChessMove] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl Extend<ChessMove> for MoveList {
+    fn extend<I: IntoIterator<Item = ChessMove>>(&mut self, moves: I) {
+        for mv in moves {
+            self.push(mv);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a ChessMove;
+    type IntoIter = ::std::slice::Iter<'a, ChessMove>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterates over a `MoveList` by value.
+pub struct IntoIter {
+    list: MoveList,
+    next: usize,
+}
+
+impl Iterator for IntoIter {
+    type Item = ChessMove;
+
+    fn next(&mut self) -> Option<ChessMove> {
+        let mv = self.list.get(self.next).cloned();
+        self.next += 1;
+        mv
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = ChessMove;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            list: self,
+            next: 0,
+        }
+    }
+}
+
+#[test]
+fn test_move_list() {
+    let square = |file, rank| BitboardPiece::from_file_rank(file, rank);
+    let mut list = MoveList::new();
+    assert!(list.is_empty());
+    for file in 0..8 {
+        list.push(ChessMove::new(square(file, 1), square(file, 3), None));
+    }
+    assert_eq!(list.len(), 8);
+    assert_eq!(list[2].from(), square(2, 1));
+
+    list.retain(|mv| mv.from().file() % 2 == 0);
+    let files: Vec<usize> = list.into_iter().map(|mv| mv.from().file()).collect();
+    assert_eq!(files, vec![0, 2, 4, 6]);
+}
+
+#[test]
+fn test_sort_by_score() {
+    let square = |file, rank| BitboardPiece::from_file_rank(file, rank);
+    let mut list = MoveList::new();
+    for file in 0..6 {
+        list.push(ChessMove::new(square(file, 1), square(file, 2), None));
+    }
+    let scores = [-100, 300, 0, -50, 300, 100];
+    let non_negative = list.sort_by_score(|mv| scores[mv.from().file()]);
+    let files: Vec<usize> = list.iter().map(|mv| mv.from().file()).collect();
+    assert_eq!(files, vec![1, 4, 5, 2, 3, 0]);
+    assert_eq!(non_negative, 4);
+    assert_eq!(MoveList::new().sort_by_score(|_| 0), 0);
+}
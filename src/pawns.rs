@@ -0,0 +1,263 @@
+use attacks::{FILE_A, FILE_H, bits};
+use chess_board::{ChessBoard, Piece};
+use evaluation::Tapered;
+
+use bitboard::Bitboard;
+
+use minimax::Team;
+
+/* Pawn structure only changes when a pawn moves or is taken, so its score
+ * is cached by the board's pawn key. The terms are worked out for whole
+ * sets of pawns at once, as White sees the board; Black's pawns are
+ * flipped vertically first, as with the piece-square tables.
+ */
+
+const DOUBLED: Tapered = Tapered { midgame: -10, endgame: -20 };
+const ISOLATED: Tapered = Tapered { midgame: -10, endgame: -15 };
+const BACKWARD: Tapered = Tapered { midgame: -8, endgame: -12 };
+/* For each pawn another one defends, which is what holds a chain together. */
+const PROTECTED: Tapered = Tapered { midgame: 5, endgame: 8 };
+
+/* The bonuses below go by rank, counted from the team's own side. */
+const PASSED_MIDGAME: [i32; 8] = [0, 5, 10, 15, 25, 45, 70, 0];
+const PASSED_ENDGAME: [i32; 8] = [0, 10, 20, 30, 50, 80, 120, 0];
+/* On top of the above when nothing at all stands in a passer's way. */
+const FREE_PATH_MIDGAME: [i32; 8] = [0, 0, 5, 10, 15, 25, 40, 0];
+const FREE_PATH_ENDGAME: [i32; 8] = [0, 5, 10, 20, 35, 60, 90, 0];
+const CANDIDATE_MIDGAME: [i32; 8] = [0, 3, 5, 8, 12, 20, 0, 0];
+const CANDIDATE_ENDGAME: [i32; 8] = [0, 5, 10, 15, 25, 40, 0, 0];
+
+const TABLE_SIZE: usize = 1 << 14;
+
+#[derive(Clone,Copy,Debug)]
+struct Entry {
+    key: u64,
+    score: Tapered,
+    /* White's passed pawns, then Black's seen from Black's side. */
+    passed: [u64; 2],
+}
+
+/// The pawn hash table. It's worth keeping one around for as long as the
+/// searches that fill it, since the same pawns come up move after move.
+#[derive(Debug)]
+pub struct PawnTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        PawnTable {
+            entries: vec![None; TABLE_SIZE],
+        }
+    }
+}
+
+/// One team's pawns sorted by what's right or wrong with them.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+struct Structure {
+    /* Pawns with another of their own somewhere in front of them. */
+    doubled: u64,
+    isolated: u64,
+    backward: u64,
+    protected: u64,
+    passed: u64,
+    /* Pawns that aren't passed yet, but have a clear file ahead and at
+     * least as many friends beside them as enemies in their way.
+     */
+    candidates: u64,
+}
+
+/// Every square on the same file as a pawn in `pawns` and above it,
+/// including its own.
+fn north_fill(mut pawns: u64) -> u64 {
+    pawns |= pawns << 8;
+    pawns |= pawns << 16;
+    pawns | (pawns << 32)
+}
+
+/// Every square on the same file as a pawn in `pawns` and below it,
+/// including its own.
+fn south_fill(mut pawns: u64) -> u64 {
+    pawns |= pawns >> 8;
+    pawns |= pawns >> 16;
+    pawns | (pawns >> 32)
+}
+
+/// The squares just to the left and right of those in `squares`.
+fn sideways(squares: u64) -> u64 {
+    ((squares & !FILE_A) >> 1) | ((squares & !FILE_H) << 1)
+}
+
+/// Sorts the pawns in `ours`, which move up the board, against the
+/// pawns in `theirs`, which move down it.
+fn classify(ours: u64, theirs: u64) -> Structure {
+    let our_attacks = ((ours & !FILE_A) << 7) | ((ours & !FILE_H) << 9);
+    let their_attacks = ((theirs & !FILE_A) >> 9) | ((theirs & !FILE_H) >> 7);
+    let their_front = south_fill(theirs) >> 8;
+
+    let doubled = ours & (south_fill(ours) >> 8);
+    let isolated = ours & !sideways(north_fill(south_fill(ours)));
+    /* Pawns that would be taken on stepping forward, with no pawn beside
+     * or behind them that could come up to defend that square.
+     */
+    let backward = ((((ours << 8) & their_attacks) & !north_fill(our_attacks)) >> 8) & !isolated;
+    let passed = ours & !(their_front | sideways(their_front)) & !doubled;
+
+    let mut candidates = 0;
+    for pawn in Bitboard::from_u64(ours & !their_front & !passed & !doubled).pieces() {
+        let beside = sideways(FILE_A << pawn.file());
+        /* Ranks up to and including the pawn's own. */
+        let behind = u64::MAX >> (56 - 8 * pawn.rank());
+        let helpers = (ours & beside & behind).count_ones();
+        let sentries = (theirs & beside & !behind).count_ones();
+        if helpers >= sentries {
+            candidates |= 1 << (pawn.rank() * 8 + pawn.file());
+        }
+    }
+
+    Structure {
+        doubled,
+        isolated,
+        backward,
+        protected: ours & our_attacks,
+        passed,
+        candidates,
+    }
+}
+
+/// Adds up the bonuses for `pawns` out of a pair of tables by rank.
+fn by_rank(pawns: u64, midgame: &[i32; 8], endgame: &[i32; 8]) -> Tapered {
+    Bitboard::from_u64(pawns).pieces()
+        .fold(Tapered::default(), |score, pawn| {
+            score + Tapered::new(midgame[pawn.rank()], endgame[pawn.rank()])
+        })
+}
+
+fn count(pawns: u64, term: Tapered) -> Tapered {
+    let n = pawns.count_ones() as i32;
+    Tapered::new(term.midgame * n, term.endgame * n)
+}
+
+/// Scores the structure of the pawns in `ours`, as `classify` sees them.
+fn structure_score(ours: u64, theirs: u64) -> (Tapered, u64) {
+    let structure = classify(ours, theirs);
+    let score = count(structure.doubled, DOUBLED) +
+                count(structure.isolated, ISOLATED) +
+                count(structure.backward, BACKWARD) +
+                count(structure.protected, PROTECTED) +
+                by_rank(structure.passed, &PASSED_MIDGAME, &PASSED_ENDGAME) +
+                by_rank(structure.candidates, &CANDIDATE_MIDGAME, &CANDIDATE_ENDGAME);
+    (score, structure.passed)
+}
+
+/// The bonus for each pawn in `passed` with nothing in `occupied`
+/// between it and promotion.
+fn free_paths(passed: u64, occupied: u64) -> Tapered {
+    let free = Bitboard::from_u64(passed).pieces()
+        .filter(|pawn| {
+            let path = north_fill(1 << (pawn.rank() * 8 + pawn.file())) << 8;
+            path & occupied == 0
+        })
+        .fold(0, |free, pawn| free | 1 << (pawn.rank() * 8 + pawn.file()));
+    by_rank(free, &FREE_PATH_MIDGAME, &FREE_PATH_ENDGAME)
+}
+
+/// Scores the pawn structure from White's side, looking the pawn terms
+/// up in `table` where it can.
+pub fn evaluate(board: &ChessBoard, table: &mut PawnTable) -> Tapered {
+    let key = board.pawn_key();
+    let index = (key % TABLE_SIZE as u64) as usize;
+    let entry = match table.entries[index] {
+        Some(entry) if entry.key == key => entry,
+        _ => {
+            let white = board.pieces_of(Team::Ally, Piece::Pawn);
+            let black = board.pieces_of(Team::Enemy, Piece::Pawn);
+            let (white_score, white_passed) = structure_score(bits(white), bits(black));
+            let (black_score, black_passed) =
+                structure_score(bits(black.flip_vertical()), bits(white.flip_vertical()));
+            let entry = Entry {
+                key,
+                score: white_score - black_score,
+                passed: [white_passed, black_passed],
+            };
+            table.entries[index] = Some(entry);
+            entry
+        }
+    };
+
+    /* Whether a passer's path is clear depends on the pieces as well, so
+     * that part can't be cached with the rest.
+     */
+    let occupied = board.occupied();
+    entry.score + free_paths(entry.passed[0], bits(occupied)) -
+    free_paths(entry.passed[1], bits(occupied.flip_vertical()))
+}
+
+#[cfg(test)]
+fn squares(names: &[&str]) -> u64 {
+    names.iter()
+        .map(|name| ::chess_board::parse_square(name).unwrap())
+        .fold(0, |squares, square| squares | 1 << (square.rank() * 8 + square.file()))
+}
+
+#[test]
+fn test_classify() {
+    let structure = classify(squares(&["a2", "b3", "c4", "e4", "e5", "h2"]),
+                             squares(&["d7", "g7"]));
+    assert_eq!(structure, Structure {
+        doubled: squares(&["e4"]),
+        isolated: squares(&["e4", "e5", "h2"]),
+        backward: 0,
+        protected: squares(&["b3", "c4"]),
+        passed: squares(&["a2", "b3"]),
+        candidates: squares(&["c4"]),
+    });
+
+    /* The d-pawn can't step up without being taken, and the e-pawn has
+     * already gone past where it could help.
+     */
+    let structure = classify(squares(&["d3", "e4"]), squares(&["c5"]));
+    assert_eq!(structure, Structure {
+        doubled: 0,
+        isolated: 0,
+        backward: squares(&["d3"]),
+        protected: squares(&["e4"]),
+        passed: squares(&["e4"]),
+        candidates: 0,
+    });
+
+    /* Two against one on the queenside makes a candidate. */
+    let structure = classify(squares(&["a2", "b3"]), squares(&["a6"]));
+    assert_eq!(structure.candidates, squares(&["b3"]));
+    assert_eq!(structure.passed, 0);
+}
+
+#[test]
+fn test_passed_pawns() {
+    let mut table = PawnTable::new();
+    let mut score = |fen| evaluate(&ChessBoard::from_fen(fen).unwrap(), &mut table);
+    /* Further up the board is worth more. */
+    let far = score("4k3/3P4/8/8/8/8/8/4K3 w - - 0 1");
+    let near = score("4k3/8/8/8/8/3P4/8/4K3 w - - 0 1");
+    assert!(far.midgame > near.midgame && far.endgame > near.endgame);
+
+    /* So is a clear path to the last rank. */
+    let blocked = score("4k3/8/3n4/3P4/8/8/8/4K3 w - - 0 1");
+    let free = score("4k3/8/n7/3P4/8/8/8/4K3 w - - 0 1");
+    assert_eq!(free - blocked, Tapered::new(FREE_PATH_MIDGAME[4], FREE_PATH_ENDGAME[4]));
+
+    /* Black's passers count against White just the same. */
+    assert_eq!(score("4k3/8/8/8/8/8/3p4/4K3 w - - 0 1"), Tapered::default() - far);
+}
+
+#[test]
+fn test_pawn_table() {
+    let board = ChessBoard::from_fen("4k3/pp3ppp/8/3p4/8/8/PP3PPP/4K3 w - - 0 1").unwrap();
+    let mut table = PawnTable::new();
+    let score = evaluate(&board, &mut table);
+    let index = (board.pawn_key() % TABLE_SIZE as u64) as usize;
+    let entry = table.entries[index].unwrap();
+    assert_eq!(entry.key, board.pawn_key());
+    assert_eq!(entry.passed, [0, squares(&["d4"])]);
+    assert_eq!(evaluate(&board, &mut table), score);
+}
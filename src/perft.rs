@@ -0,0 +1,117 @@
+use chess_board::{ChessBoard, ChessMove};
+
+/// Counts the leaf nodes of the legal move tree `depth` plies deep.
+pub fn perft(board: &ChessBoard, depth: u32) -> u64 {
+    perft_in_place(&mut board.clone(), depth)
+}
+
+/* Walks the tree with do_move and undo_move rather than copying the
+ * board at every node.
+ */
+fn perft_in_place(board: &mut ChessBoard, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = board.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves.iter() {
+        let undo = board.do_move(mv);
+        nodes += perft_in_place(board, depth - 1);
+        board.undo_move(&undo);
+    }
+    nodes
+}
+
+/// Splits the perft count by the first move, which makes it easy to
+/// narrow a wrong total down to the move generator bug behind it. At
+/// depth 0 there's no first move to split by, so the list is empty even
+/// though `perft` counts the position itself.
+pub fn perft_divide(board: &ChessBoard, depth: u32) -> Vec<(ChessMove, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+
+    board.legal_moves()
+        .into_iter()
+        .map(|mv| {
+            let mut child = board.clone();
+            child.do_move(&mv);
+            (mv, perft(&child, depth - 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn check_perft(fen: &str, counts: &[u64]) {
+    let board = ChessBoard::from_fen(fen).unwrap();
+    for (depth, &count) in counts.iter().enumerate() {
+        assert_eq!(perft(&board, depth as u32 + 1), count, "{} at depth {}", fen, depth + 1);
+    }
+}
+
+#[test]
+fn test_perft_initial() {
+    check_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                &[20, 400, 8902, 197281]);
+}
+
+#[test]
+fn test_perft_kiwipete() {
+    check_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862]);
+}
+
+#[test]
+fn test_perft_position_3() {
+    check_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                &[14, 191, 2812, 43238]);
+}
+
+#[test]
+fn test_perft_position_4() {
+    check_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                &[6, 264, 9467]);
+    check_perft("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+                &[6, 264, 9467]);
+}
+
+#[test]
+fn test_perft_position_5() {
+    check_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                &[44, 1486, 62379]);
+}
+
+#[test]
+fn test_perft_position_6() {
+    check_perft("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                &[46, 2079, 89890]);
+}
+
+#[test]
+fn test_perft_divide() {
+    let board = ChessBoard::new();
+    let divide = perft_divide(&board, 2);
+    assert_eq!(divide.len(), 20);
+    assert!(divide.iter().all(|&(_, count)| count == 20));
+    assert_eq!(divide.iter().map(|&(_, count)| count).sum::<u64>(), perft(&board, 2));
+
+    assert!(perft_divide(&board, 0).is_empty());
+    assert_eq!(perft(&board, 0), 1);
+}
+
+/* These take a while in debug builds; run them with `cargo test -- --ignored`. */
+#[test]
+#[ignore]
+fn test_perft_deep() {
+    check_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                &[20, 400, 8902, 197281, 4865609]);
+    check_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862, 4085603]);
+    check_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                &[14, 191, 2812, 43238, 674624]);
+}
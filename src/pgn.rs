@@ -0,0 +1,533 @@
+use chess_board::{ChessBoard, ChessMove, FenError};
+use san::SanError;
+
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/* The Seven Tag Roster, which every exported game starts with. */
+const ROSTER: [(&str, &str); 7] = [("Event", "?"),
+                                   ("Site", "?"),
+                                   ("Date", "????.??.??"),
+                                   ("Round", "?"),
+                                   ("White", "?"),
+                                   ("Black", "?"),
+                                   ("Result", "*")];
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum PgnResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game is unfinished or its result is unknown.
+    Unknown,
+}
+
+impl PgnResult {
+    fn from_str(s: &str) -> Option<PgnResult> {
+        match s {
+            "1-0" => Some(PgnResult::WhiteWins),
+            "0-1" => Some(PgnResult::BlackWins),
+            "1/2-1/2" => Some(PgnResult::Draw),
+            "*" => Some(PgnResult::Unknown),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PgnResult {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PgnResult::WhiteWins => write!(fmt, "1-0"),
+            PgnResult::BlackWins => write!(fmt, "0-1"),
+            PgnResult::Draw => write!(fmt, "1/2-1/2"),
+            PgnResult::Unknown => write!(fmt, "*"),
+        }
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum PgnErrorKind {
+    UnterminatedString,
+    UnterminatedComment,
+    /// A tag pair wasn't of the form `[Name "value"]`.
+    BadTag,
+    /// A `)` without a matching `(`, or a game ending inside a variation.
+    UnbalancedVariation,
+    UnexpectedChar(char),
+    /// The movetext ran out without a result such as "1-0" or "*".
+    MissingResult,
+    BadFen(FenError),
+    BadMove(SanError),
+}
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct PgnError {
+    /// Line of the offending text, starting at 1.
+    pub line: usize,
+    /// Column of the offending text, starting at 1.
+    pub column: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}, column {}: ", self.line, self.column)?;
+        match self.kind {
+            PgnErrorKind::UnterminatedString => write!(fmt, "unterminated string"),
+            PgnErrorKind::UnterminatedComment => write!(fmt, "unterminated comment"),
+            PgnErrorKind::BadTag => write!(fmt, "tag pairs look like [Name \"value\"]"),
+            PgnErrorKind::UnbalancedVariation => write!(fmt, "unbalanced variation"),
+            PgnErrorKind::UnexpectedChar(c) => write!(fmt, "unexpected '{}'", c),
+            PgnErrorKind::MissingResult => write!(fmt, "game has no result"),
+            PgnErrorKind::BadFen(ref e) => write!(fmt, "bad FEN tag: {}", e),
+            PgnErrorKind::BadMove(ref e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+impl Error for PgnError {}
+
+#[derive(Clone,Debug)]
+pub struct PgnGame {
+    /// Tag pairs in the order they were read or set.
+    pub tags: Vec<(String, String)>,
+    /// The position before the first move.
+    pub start: ChessBoard,
+    pub moves: Vec<ChessMove>,
+    pub result: PgnResult,
+}
+
+impl PgnGame {
+    pub fn new(start: ChessBoard) -> PgnGame {
+        PgnGame {
+            tags: Vec::new(),
+            start,
+            moves: Vec::new(),
+            result: PgnResult::Unknown,
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|t| t.0 == name).map(|t| &t.1[..])
+    }
+
+    /// Sets a tag, replacing any earlier value for the same name.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|t| t.0 == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Returns the position after the first `ply` moves have been played.
+    pub fn board_at(&self, ply: usize) -> ChessBoard {
+        let mut board = self.start.clone();
+        for mv in self.moves.iter().take(ply) {
+            board.do_move(mv);
+        }
+        board
+    }
+
+    /// Writes the game as PGN: the Seven Tag Roster, any setup and custom
+    /// tags, then SAN movetext wrapped to 80 columns and the result.
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let result = self.result.to_string();
+
+        for &(name, default) in ROSTER.iter() {
+            let value = if name == "Result" {
+                &result[..]
+            } else {
+                self.tag(name).unwrap_or(default)
+            };
+            pgn.push_str(&format_tag(name, value));
+        }
+
+        let fen = self.start.to_fen();
+        if fen != START_FEN {
+            pgn.push_str(&format_tag("SetUp", "1"));
+            pgn.push_str(&format_tag("FEN", &fen));
+        }
+
+        for (name, value) in self.tags.iter() {
+            let is_written = ROSTER.iter().any(|r| r.0 == *name) || *name == "SetUp" || *name == "FEN";
+            if !is_written {
+                pgn.push_str(&format_tag(name, value));
+            }
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut board = self.start.clone();
+        let mut ally_to_move = fen.split(' ').nth(1) == Some("w");
+        let mut move_number: u32 = fen.split(' ').nth(5).and_then(|n| n.parse().ok()).unwrap_or(1);
+        for (ply, mv) in self.moves.iter().enumerate() {
+            if ally_to_move {
+                tokens.push(format!("{}.", move_number));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", move_number));
+            }
+            tokens.push(board.move_to_san(mv));
+            board.do_move(mv);
+
+            if !ally_to_move {
+                move_number += 1;
+            }
+            ally_to_move = !ally_to_move;
+        }
+        tokens.push(result);
+
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len > 0 && line_len + 1 + token.len() > 80 {
+                pgn.push('\n');
+                line_len = 0;
+            }
+            if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push('\n');
+
+        pgn
+    }
+}
+
+fn format_tag(name: &str, value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", name, escaped)
+}
+
+/// Reads every game in a PGN file. Comments, NAGs and variations are
+/// skipped, leaving the main line as moves on the starting position.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut games = Vec::new();
+
+    while let Some(game) = parser.parse_game()? {
+        games.push(game);
+    }
+
+    Ok(games)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, kind: PgnErrorKind) -> PgnError {
+        PgnError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else if c.is_some() {
+            self.column += 1;
+        }
+        c
+    }
+
+    /// Skips whitespace, comments and escaped lines.
+    fn skip_filler(&mut self) -> Result<(), PgnError> {
+        loop {
+            match self.chars.peek() {
+                Some(&'%') if self.column == 1 => self.skip_line(),
+                Some(&';') => self.skip_line(),
+                Some(&'{') => {
+                    let error = self.error(PgnErrorKind::UnterminatedComment);
+                    loop {
+                        match self.bump() {
+                            Some('}') => break,
+                            Some(_) => {}
+                            None => return Err(error),
+                        }
+                    }
+                }
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn read_symbol(&mut self) -> String {
+        let mut symbol = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_alphanumeric() || "_+#=:-/.!?*".contains(c) {
+                symbol.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        symbol
+    }
+
+    fn read_tag(&mut self) -> Result<(String, String), PgnError> {
+        self.bump();
+        self.skip_filler()?;
+        let name = self.read_symbol();
+        if name.is_empty() {
+            return Err(self.error(PgnErrorKind::BadTag));
+        }
+        self.skip_filler()?;
+        if self.chars.peek() != Some(&'"') {
+            return Err(self.error(PgnErrorKind::BadTag));
+        }
+
+        let unterminated = self.error(PgnErrorKind::UnterminatedString);
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => match self.bump() {
+                    Some(c) => value.push(c),
+                    None => return Err(unterminated),
+                },
+                Some('\n') | None => return Err(unterminated),
+                Some(c) => value.push(c),
+            }
+        }
+
+        self.skip_filler()?;
+        if self.bump() != Some(']') {
+            return Err(self.error(PgnErrorKind::BadTag));
+        }
+        Ok((name, value))
+    }
+
+    fn parse_game(&mut self) -> Result<Option<PgnGame>, PgnError> {
+        let mut tags = Vec::new();
+        self.skip_filler()?;
+        while self.chars.peek() == Some(&'[') {
+            tags.push(self.read_tag()?);
+            self.skip_filler()?;
+        }
+
+        if tags.is_empty() && self.chars.peek().is_none() {
+            return Ok(None);
+        }
+
+        let start = match tags.iter().find(|t| t.0 == "FEN") {
+            Some((_, fen)) => {
+                ChessBoard::from_fen(fen).map_err(|e| self.error(PgnErrorKind::BadFen(e)))?
+            }
+            None => ChessBoard::new(),
+        };
+        let mut game = PgnGame::new(start);
+        game.tags = tags;
+        let mut board = game.start.clone();
+        let mut depth = 0;
+
+        loop {
+            self.skip_filler()?;
+            let (line, column) = (self.line, self.column);
+            match self.chars.peek().cloned() {
+                None | Some('[') => return Err(self.error(PgnErrorKind::MissingResult)),
+                Some('(') => {
+                    self.bump();
+                    depth += 1;
+                }
+                Some(')') => {
+                    if depth == 0 {
+                        return Err(self.error(PgnErrorKind::UnbalancedVariation));
+                    }
+                    self.bump();
+                    depth -= 1;
+                }
+                Some('$') => {
+                    self.bump();
+                    while self.chars.peek().iter().any(|c| c.is_ascii_digit()) {
+                        self.bump();
+                    }
+                }
+                Some(c) => {
+                    let symbol = self.read_symbol();
+                    if symbol.is_empty() {
+                        return Err(self.error(PgnErrorKind::UnexpectedChar(c)));
+                    }
+
+                    if let Some(result) = PgnResult::from_str(&symbol) {
+                        if depth > 0 {
+                            return Err(self.error(PgnErrorKind::UnbalancedVariation));
+                        }
+                        game.result = result;
+                        return Ok(Some(game));
+                    }
+
+                    /* Move numbers may run straight into the move, as in "1.e4".
+                     * Only digits followed by a dot count, so "0-0" survives.
+                     */
+                    let digits = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let is_move_number = digits.len() < symbol.len() && digits.starts_with('.');
+                    let san = if is_move_number {
+                        digits.trim_start_matches('.')
+                    } else {
+                        &symbol[..]
+                    };
+                    if san.is_empty() || depth > 0 {
+                        continue;
+                    }
+
+                    let mv = board.move_from_san(san).map_err(|e| {
+                        PgnError {
+                            line,
+                            column,
+                            kind: PgnErrorKind::BadMove(e),
+                        }
+                    })?;
+                    board.do_move(&mv);
+                    game.moves.push(mv);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pgn_write() {
+    let mut board = ChessBoard::new();
+    let mut game = PgnGame::new(board.clone());
+    for san in ["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6", "Qxf7#"].iter() {
+        let mv = board.move_from_san(san).unwrap();
+        board.do_move(&mv);
+        game.moves.push(mv);
+    }
+    game.result = PgnResult::WhiteWins;
+    game.set_tag("White", "Scholar \"The\" Mate");
+    game.set_tag("Annotator", "rusty");
+
+    assert_eq!(game.to_pgn(),
+               "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+                [White \"Scholar \\\"The\\\" Mate\"]\n[Black \"?\"]\n[Result \"1-0\"]\n\
+                [Annotator \"rusty\"]\n\n\
+                1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6 4. Qxf7# 1-0\n");
+}
+
+#[test]
+fn test_pgn_write_from_fen() {
+    let start = ChessBoard::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 30").unwrap();
+    let mut game = PgnGame::new(start.clone());
+    game.moves.push(start.move_from_san("Kd7").unwrap());
+
+    let pgn = game.to_pgn();
+    assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 30\"]\n"));
+    assert!(pgn.ends_with("\n30... Kd7 *\n"));
+}
+
+#[test]
+fn test_pgn_round_trip() {
+    let mut board = ChessBoard::new();
+    let mut game = PgnGame::new(board.clone());
+    /* Long enough to wrap, with castling, promotion and an en passant. */
+    for san in ["e4", "d5", "e5", "f5", "exf6", "Nc6", "fxg7", "Be6", "gxh8=Q", "Qd7",
+                "Nf3", "O-O-O", "Bb5", "a6", "O-O", "axb5", "Qxg8", "Kb8"].iter() {
+        let mv = board.move_from_san(san).unwrap();
+        board.do_move(&mv);
+        game.moves.push(mv);
+    }
+    game.result = PgnResult::Draw;
+
+    let pgn = game.to_pgn();
+    assert!(pgn.lines().all(|line| line.len() <= 80));
+
+    let games = parse_pgn(&pgn).unwrap();
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].moves, game.moves);
+    assert_eq!(games[0].result, PgnResult::Draw);
+    assert_eq!(games[0].board_at(game.moves.len()), board);
+}
+
+#[test]
+fn test_pgn_parse_multiple_games() {
+    let text = "% exported by some tool\n\
+                [Event \"First\"]\n\
+                [Result \"1-0\"]\n\
+                \n\
+                1. e4 {best by test} e5 $1 2. Nf3 (2. f4 exf4 (2... d5) 3. Nf3) 2... Nc6 ; rest\n\
+                3.Bb5!? a6 1-0\n\
+                \n\
+                [Event \"Second\"]\n\
+                [SetUp \"1\"]\n\
+                [FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\
+                \n\
+                1. e4 Kd7 *\n";
+    let games = parse_pgn(text).unwrap();
+    assert_eq!(games.len(), 2);
+
+    assert_eq!(games[0].tag("Event"), Some("First"));
+    assert_eq!(games[0].result, PgnResult::WhiteWins);
+    assert_eq!(games[0].moves.len(), 6);
+    assert_eq!(games[0].board_at(6).to_fen(),
+               "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4");
+
+    assert_eq!(games[1].tag("Event"), Some("Second"));
+    assert_eq!(games[1].result, PgnResult::Unknown);
+    assert_eq!(games[1].board_at(2).to_fen(), "8/3k4/8/8/4P3/8/8/4K3 w - - 1 2");
+}
+
+#[test]
+fn test_pgn_errors() {
+    let error = |text: &str| parse_pgn(text).unwrap_err();
+
+    let e = error("[Event \"x\"]\n\n1. e4 e5 2. Ke3 *\n");
+    assert_eq!((e.line, e.column), (3, 13));
+    assert_eq!(e.kind, PgnErrorKind::BadMove(SanError::Illegal("Ke3".to_string())));
+
+    let e = error("1. e4 {unfinished\n\n");
+    assert_eq!((e.line, e.column, e.kind), (1, 7, PgnErrorKind::UnterminatedComment));
+
+    let e = error("[Event \"x]\n1. e4 *");
+    assert_eq!((e.line, e.column, e.kind), (1, 8, PgnErrorKind::UnterminatedString));
+
+    let e = error("1. e4 e5 2. Nf3 )\n*");
+    assert_eq!((e.line, e.column, e.kind), (1, 17, PgnErrorKind::UnbalancedVariation));
+
+    let e = error("1. e4 e5\n");
+    assert_eq!((e.line, e.column, e.kind), (2, 1, PgnErrorKind::MissingResult));
+
+    let e = error("[FEN \"8/8 w - -\"]\n*");
+    assert_eq!(e.kind, PgnErrorKind::BadFen(FenError::RankCount(2)));
+}
+
+#[test]
+fn test_pgn_parse_zero_castling() {
+    let games = parse_pgn("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 *\n").unwrap();
+    assert_eq!(games[0].moves.len(), 7);
+    assert_eq!(games[0].board_at(7).to_fen(),
+               "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 5 4");
+
+    let games = parse_pgn("1.e4 e5 2.Nf3 Nf6 3.Be2 Be7 4.0-0 0-0 *\n").unwrap();
+    assert_eq!(games[0].moves.len(), 8);
+}
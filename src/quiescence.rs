@@ -0,0 +1,98 @@
+use chess_board::ChessBoard;
+use evaluation;
+use pawns::PawnTable;
+use search::Control;
+
+use minimax::Team;
+
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/* Quiescence search carries on past the end of the main search, playing
+ * out only captures and promotions until nothing is left hanging. Leaves
+ * scored in the middle of an exchange would otherwise count a piece as
+ * won just before it gets taken back.
+ */
+
+/// Scores `board` from White's side once the captures and promotions on
+/// it have played out, counting each position against `control` and
+/// scoring pawns out of its table. The
+/// moves are made and taken back on `board` itself, which ends up as it
+/// started.
+pub fn evaluate(board: &mut ChessBoard, control: &Control) -> i32 {
+    let mut pawns = control.pawns();
+    let score = search(board, control, &mut pawns, -i32::MAX, i32::MAX);
+    match board.turn() {
+        Team::Ally => score,
+        Team::Enemy => -score,
+    }
+}
+
+/// An alpha-beta search over captures and promotions, scored from the
+/// side of the team to move.
+fn search(board: &mut ChessBoard, control: &Control, pawns: &mut PawnTable,
+          mut alpha: i32, beta: i32) -> i32 {
+    control.count_quiescence_node();
+    let us = board.turn();
+
+    /* The team to move doesn't have to capture anything, so it can
+     * usually settle for the position as it stands.
+     */
+    let stand_pat = match us {
+        Team::Ally => evaluation::evaluate(board, pawns),
+        Team::Enemy => -evaluation::evaluate(board, pawns),
+    };
+    if stand_pat >= beta {
+        return stand_pat;
+    }
+    alpha = alpha.max(stand_pat);
+    if control.visit() {
+        return alpha;
+    }
+
+    /* Captures that lose material on the exchange are left out. */
+    let mut moves = board.tactical_moves(us);
+    let winning = moves.sort_by_score(|mv| board.see(mv));
+    for mv in moves[..winning].iter() {
+        let undo = board.do_move(mv);
+        let score = -search(board, control, pawns, -beta, -alpha);
+        board.undo_move(&undo);
+        if score >= beta {
+            return score;
+        }
+        alpha = alpha.max(score);
+    }
+    alpha
+}
+
+#[cfg(test)]
+fn control() -> Control {
+    Control::new(None, Arc::new(Mutex::new(PawnTable::new())))
+}
+
+#[test]
+fn test_quiescence_sees_recaptures() {
+    let control = control();
+
+    /* Black wins the queen back for a pawn. */
+    let mut board = ChessBoard::from_fen("4k3/8/4p3/3Q4/8/8/8/4K3 b - - 0 1").unwrap();
+    assert!(evaluation::evaluate(&board, &mut control.pawns()) > 700);
+    assert!(evaluate(&mut board, &control) < 0);
+
+    /* White doesn't take the pawn, since it's defended. */
+    let mut board = ChessBoard::from_fen("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+    assert_eq!(evaluate(&mut board, &control), evaluation::evaluate(&board, &mut control.pawns()));
+
+    /* But it does take a hanging queen. */
+    let mut board = ChessBoard::from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1").unwrap();
+    assert!(evaluation::evaluate(&board, &mut control.pawns()) < -300);
+    assert!(evaluate(&mut board, &control) > 400);
+}
+
+#[test]
+fn test_quiescence_quiet_position() {
+    let control = control();
+    let mut board = ChessBoard::new();
+    assert_eq!(evaluate(&mut board, &control), 0);
+    assert_eq!(control.quiescence_nodes(), 1);
+}
@@ -0,0 +1,247 @@
+use chess_board::{ChessBoard, ChessMove, GameOutcome, Piece, parse_square, square_name};
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum SanError {
+    /// The text isn't shaped like a SAN move.
+    Malformed(String),
+    /// No legal move matches the text.
+    Illegal(String),
+    /// More than one legal move matches the text.
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SanError::Malformed(ref s) => write!(fmt, "'{}' isn't a SAN move", s),
+            SanError::Illegal(ref s) => write!(fmt, "'{}' isn't a legal move here", s),
+            SanError::Ambiguous(ref s) => write!(fmt, "'{}' could mean more than one move", s),
+        }
+    }
+}
+
+impl Error for SanError {}
+
+fn is_castle(board: &ChessBoard, mv: &ChessMove) -> bool {
+    board.piece_at(mv.from()) == Some(Piece::King) &&
+    (mv.from().file() as i32 - mv.to().file() as i32).abs() == 2
+}
+
+impl ChessBoard {
+    /// Parses a move in Standard Algebraic Notation, such as "Nf3",
+    /// "exd5", "O-O" or "e8=Q+", for the team to move. Check and
+    /// annotation suffixes are accepted but not verified.
+    pub fn move_from_san(&self, s: &str) -> Result<ChessMove, SanError> {
+        let malformed = || SanError::Malformed(s.to_string());
+        let text = s.trim().trim_end_matches(|c| "+#!?".contains(c));
+        let moves = self.legal_moves();
+
+        let castle_file = match text {
+            "O-O" | "0-0" => Some(6),
+            "O-O-O" | "0-0-0" => Some(2),
+            _ => None,
+        };
+        if let Some(file) = castle_file {
+            return moves.into_iter()
+                .find(|mv| is_castle(self, mv) && mv.to().file() == file)
+                .ok_or_else(|| SanError::Illegal(s.to_string()));
+        }
+
+        let mut chars: Vec<char> = text.chars().collect();
+
+        let piece = match chars.first().and_then(|&c| Piece::from_char(c)) {
+            Some(piece) if chars[0].is_uppercase() => {
+                chars.remove(0);
+                piece
+            }
+            _ => Piece::Pawn,
+        };
+
+        /* Promotions may be written "e8=Q" or just "e8Q". */
+        let mut promotion = None;
+        if chars.len() > 2 {
+            let last = chars[chars.len() - 1];
+            if last.is_uppercase() {
+                match Piece::from_char(last) {
+                    Some(Piece::Pawn) | Some(Piece::King) | None => return Err(malformed()),
+                    p => promotion = p,
+                }
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        if chars.len() < 2 {
+            return Err(malformed());
+        }
+        let dest: String = chars[chars.len() - 2..].iter().collect();
+        let to = parse_square(&dest).ok_or_else(malformed)?;
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in chars[..chars.len() - 2].iter().filter(|&&c| c != 'x') {
+            match c {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                    from_file = Some(c as usize - 'a' as usize);
+                }
+                '1'..='8' if from_rank.is_none() => {
+                    from_rank = Some(c as usize - '1' as usize);
+                }
+                _ => return Err(malformed()),
+            }
+        }
+
+        let mut matches = moves.into_iter().filter(|mv| {
+            mv.to() == to &&
+            mv.promotion() == promotion &&
+            self.piece_at(mv.from()) == Some(piece) &&
+            from_file.iter().all(|&f| mv.from().file() == f) &&
+            from_rank.iter().all(|&r| mv.from().rank() == r)
+        });
+
+        match (matches.next(), matches.next()) {
+            (Some(mv), None) => Ok(mv),
+            (Some(_), Some(_)) => Err(SanError::Ambiguous(s.to_string())),
+            (None, _) => Err(SanError::Illegal(s.to_string())),
+        }
+    }
+
+    /// Formats a legal move for the team to move in Standard Algebraic
+    /// Notation, with the least disambiguation needed and a check or
+    /// mate suffix.
+    pub fn move_to_san(&self, mv: &ChessMove) -> String {
+        let mut san = String::new();
+        let piece = self.piece_at(mv.from()).unwrap_or(Piece::Pawn);
+        let is_capture = self.piece_at(mv.to()).is_some() ||
+                         (piece == Piece::Pawn && mv.from().file() != mv.to().file());
+
+        if is_castle(self, mv) {
+            san.push_str(if mv.to().file() == 6 { "O-O" } else { "O-O-O" });
+        } else {
+            if piece == Piece::Pawn {
+                if is_capture {
+                    san.push((b'a' + mv.from().file() as u8) as char);
+                }
+            } else {
+                san.push(piece.to_char());
+
+                let rivals: Vec<ChessMove> = self.legal_moves()
+                    .into_iter()
+                    .filter(|other| {
+                        other.to() == mv.to() && other.from() != mv.from() &&
+                        self.piece_at(other.from()) == Some(piece)
+                    })
+                    .collect();
+                if !rivals.is_empty() {
+                    let square = square_name(mv.from());
+                    if rivals.iter().all(|other| other.from().file() != mv.from().file()) {
+                        san.push_str(&square[..1]);
+                    } else if rivals.iter().all(|other| other.from().rank() != mv.from().rank()) {
+                        san.push_str(&square[1..]);
+                    } else {
+                        san.push_str(&square);
+                    }
+                }
+            }
+
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.to()));
+
+            if let Some(promotion) = mv.promotion() {
+                san.push('=');
+                san.push(promotion.to_char());
+            }
+        }
+
+        let mut after = self.clone();
+        after.do_move(mv);
+        match after.outcome() {
+            GameOutcome::Checkmate(_) => san.push('#'),
+            _ if after.in_check() => san.push('+'),
+            _ => {}
+        }
+
+        san
+    }
+}
+
+#[cfg(test)]
+fn san_round_trip(fen: &str, san: &str, coordinates: &str) {
+    let board = ChessBoard::from_fen(fen).unwrap();
+    let mv = board.move_from_str(coordinates).unwrap();
+    assert_eq!(board.move_from_san(san), Ok(mv));
+    assert_eq!(board.move_to_san(&mv), san);
+}
+
+#[test]
+fn test_san_simple_moves() {
+    let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    san_round_trip(start, "e4", "E2E4");
+    san_round_trip(start, "Nf3", "G1F3");
+
+    let black = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+    san_round_trip(black, "Nc6", "B8C6");
+    san_round_trip(black, "d5", "D7D5");
+}
+
+#[test]
+fn test_san_captures_and_castling() {
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    san_round_trip(fen, "O-O", "E1G1");
+    san_round_trip(fen, "O-O-O", "E1C1");
+    san_round_trip(fen, "dxe6", "D5E6");
+    san_round_trip(fen, "Nxf7", "E5F7");
+    san_round_trip(fen, "Bxa6", "E2A6");
+    san_round_trip(fen, "gxh3", "G2H3");
+
+    let en_passant = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+    san_round_trip(en_passant, "exf6", "E5F6");
+}
+
+#[test]
+fn test_san_disambiguation() {
+    let files = "4k3/8/8/8/8/8/4K3/R6R w - - 0 1";
+    san_round_trip(files, "Rad1", "A1D1");
+    san_round_trip(files, "Rhf1", "H1F1");
+
+    let ranks = "7k/R7/8/8/8/8/8/R3K3 w - - 0 1";
+    san_round_trip(ranks, "R1a4", "A1A4");
+    san_round_trip(ranks, "R7a4", "A7A4");
+
+    let squares = "8/5k2/8/8/Q6Q/8/8/Q3K3 w - - 0 1";
+    san_round_trip(squares, "Qa4d4", "A4D4");
+    san_round_trip(squares, "Q1d4", "A1D4");
+
+    let board = ChessBoard::from_fen(files).unwrap();
+    assert_eq!(board.move_from_san("Rd1"), Err(SanError::Ambiguous("Rd1".to_string())));
+    assert_eq!(board.move_from_san("Raxd1"), Ok(board.move_from_str("A1D1").unwrap()));
+}
+
+#[test]
+fn test_san_promotion_and_mate() {
+    let fen = "3r3k/4P3/6K1/8/8/8/8/8 w - - 0 1";
+    san_round_trip(fen, "exd8=Q#", "E7D8Q");
+    san_round_trip(fen, "e8=N", "E7E8N");
+    san_round_trip(fen, "exd8=R#", "E7D8R");
+
+    let board = ChessBoard::from_fen(fen).unwrap();
+    assert_eq!(board.move_from_san("exd8Q"), board.move_from_san("exd8=Q#"));
+    assert_eq!(board.move_from_san("e8=Q+"), Ok(board.move_from_str("E7E8Q").unwrap()));
+}
+
+#[test]
+fn test_san_errors() {
+    let board = ChessBoard::new();
+    assert_eq!(board.move_from_san("e5"), Err(SanError::Illegal("e5".to_string())));
+    assert_eq!(board.move_from_san("O-O"), Err(SanError::Illegal("O-O".to_string())));
+    assert_eq!(board.move_from_san("Nz3"), Err(SanError::Malformed("Nz3".to_string())));
+    assert_eq!(board.move_from_san("e"), Err(SanError::Malformed("e".to_string())));
+    assert_eq!(board.move_from_san("e8=K"), Err(SanError::Malformed("e8=K".to_string())));
+}
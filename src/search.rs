@@ -0,0 +1,614 @@
+use chess_board::{ChessBoard, ChessMove, GameOutcome};
+use pawns::PawnTable;
+use quiescence;
+
+use minimax::board::Board;
+use minimax::{Minimax, Score, Team};
+
+use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/* The search driver behind the protocol front-ends. Each search deepens
+ * one ply at a time on a worker thread so the front-end can keep reading
+ * input and answer the moment it's told to move or runs out of time.
+ * minimax() itself can't be interrupted, so to call a search off its
+ * SearchBoards start reporting every position as game over, which unwinds
+ * it almost immediately. */
+
+/* Used when there's no limit at all. */
+const DEFAULT_DEPTH: u32 = 6;
+pub const MAX_DEPTH: u32 = 64;
+const TABLE_SIZE: usize = 1000000;
+
+/// Shared between one search's worker and the thread waiting on it.
+#[derive(Debug)]
+pub struct Control {
+    stopped: AtomicBool,
+    nodes: AtomicU64,
+    node_limit: u64,
+    quiescence_nodes: AtomicU64,
+    pawns: Arc<Mutex<PawnTable>>,
+}
+
+impl Control {
+    pub fn new(node_limit: Option<u64>, pawns: Arc<Mutex<PawnTable>>) -> Control {
+        Control {
+            stopped: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+            node_limit: node_limit.unwrap_or(u64::MAX),
+            quiescence_nodes: AtomicU64::new(0),
+            pawns,
+        }
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the search has been called off or has used up its nodes.
+    pub fn cancelled(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst) || self.nodes() >= self.node_limit
+    }
+
+    /// Counts a node against the search's limit, then checks `cancelled`.
+    pub fn visit(&self) -> bool {
+        self.nodes.fetch_add(1, Ordering::Relaxed);
+        self.cancelled()
+    }
+
+    fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    /// Counts a position quiescence search has looked at. These are kept
+    /// apart from `nodes`, which only counts the ones that got expanded.
+    pub fn count_quiescence_node(&self) {
+        self.quiescence_nodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn quiescence_nodes(&self) -> u64 {
+        self.quiescence_nodes.load(Ordering::Relaxed)
+    }
+
+    /// The pawn table the search's evaluations share.
+    pub fn pawns(&self) -> MutexGuard<'_, PawnTable> {
+        self.pawns.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A position as minimax sees it. Every board in one search shares its
+/// `Control`, which is how the nodes get counted and the search called off
+/// without the board itself knowing anything about it.
+#[derive(Debug)]
+pub struct SearchBoard {
+    /* Quiescence search plays its captures out on the board and takes
+     * them back, which `score` can only do from behind a shared reference.
+     */
+    board: RefCell<ChessBoard>,
+    control: Arc<Control>,
+}
+
+impl SearchBoard {
+    /// Starts a search of `board` with no limits of its own, scoring pawns
+    /// out of `pawns`.
+    pub fn new(board: ChessBoard, pawns: Arc<Mutex<PawnTable>>) -> SearchBoard {
+        SearchBoard::with_control(board, Arc::new(Control::new(None, pawns)))
+    }
+
+    /// Returns how many positions quiescence search has looked at in the
+    /// search this board started.
+    pub fn quiescence_nodes(&self) -> u64 {
+        self.control.quiescence_nodes()
+    }
+
+    fn with_control(board: ChessBoard, control: Arc<Control>) -> SearchBoard {
+        SearchBoard { board: RefCell::new(board), control }
+    }
+}
+
+/* minimax clones the board for every move it tries, and each clone still
+ * allocates a copy of the repeatable history. */
+impl Clone for SearchBoard {
+    fn clone(&self) -> SearchBoard {
+        SearchBoard::with_control(self.board.borrow().clone_for_search(), self.control.clone())
+    }
+}
+
+impl PartialEq for SearchBoard {
+    fn eq(&self, other: &SearchBoard) -> bool {
+        *self.board.borrow() == *other.board.borrow()
+    }
+}
+
+impl Eq for SearchBoard {}
+
+impl Hash for SearchBoard {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.board.borrow().hash(state);
+    }
+}
+
+impl Board for SearchBoard {
+    type Move = ChessMove;
+
+    /* The trait wants a Vec, so the ordered moves get copied into one
+     * at every node the search expands.
+     */
+    fn gen_ally_moves(&self) -> Vec<Self::Move> {
+        self.board.borrow().gen_ordered_moves(Team::Ally)
+    }
+
+    fn gen_enemy_moves(&self) -> Vec<Self::Move> {
+        self.board.borrow().gen_ordered_moves(Team::Enemy)
+    }
+
+    fn do_move(&mut self, mv: &Self::Move) {
+        self.board.get_mut().do_move(mv);
+    }
+
+    fn score(&self) -> Score {
+        /* A search that's been called off only wants to unwind. */
+        if self.control.cancelled() {
+            return Score::Heuristic(0);
+        }
+        let mut board = self.board.borrow_mut();
+        match board.outcome() {
+            GameOutcome::Checkmate(Team::Ally) => Score::Win,
+            GameOutcome::Checkmate(Team::Enemy) => Score::Lose,
+            GameOutcome::Stalemate | GameOutcome::Draw(_) => Score::Heuristic(0),
+            GameOutcome::Ongoing => Score::Heuristic(quiescence::evaluate(&mut board, &self.control)),
+        }
+    }
+
+    fn is_game_over(&self) -> bool {
+        self.control.visit() || !matches!(self.board.borrow().outcome(), GameOutcome::Ongoing)
+    }
+}
+
+/// How long to think about a move.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Budget {
+    /// Don't start a depth that isn't likely to finish within this.
+    pub soft: Duration,
+    /// Call the search off here, finished or not.
+    pub hard: Duration,
+}
+
+impl Budget {
+    pub fn fixed(time: Duration) -> Budget {
+        Budget { soft: time, hard: time }
+    }
+
+    /// Shares out what's `left` on the clock between the next
+    /// `moves_to_go` moves.
+    pub fn from_clock(left: Duration, increment: Duration, moves_to_go: u32) -> Budget {
+        let share = left / moves_to_go.max(1) + increment / 2;
+        /* Never plan to spend more than half of what's left. */
+        Budget {
+            soft: share.min(left / 2),
+            hard: (share * 3).min(left / 2),
+        }
+    }
+}
+
+/// When to stop searching.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Limits {
+    pub max_depth: u32,
+    pub budget: Option<Budget>,
+    pub nodes: Option<u64>,
+    /// Keep searching, and then keep waiting, until told to stop.
+    pub infinite: bool,
+}
+
+impl Limits {
+    /// Searches to `depth` if given, and otherwise as deep as the other
+    /// limits allow.
+    pub fn new(depth: Option<u32>, budget: Option<Budget>, nodes: Option<u64>,
+               infinite: bool) -> Limits {
+        let max_depth = match depth {
+            Some(depth) => depth.clamp(1, MAX_DEPTH),
+            None if infinite || budget.is_some() || nodes.is_some() => MAX_DEPTH,
+            None => DEFAULT_DEPTH,
+        };
+        Limits { max_depth, budget, nodes, infinite }
+    }
+}
+
+/// A search score from the point of view of the team the engine plays.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum EngineScore {
+    /// Mate in this many moves, negative when it's the engine getting
+    /// mated.
+    Mate(i32),
+    Centipawns(i32),
+}
+
+impl EngineScore {
+    pub fn new(score: Score, turns: u32, turn: Team) -> EngineScore {
+        let sign = match turn {
+            Team::Ally => 1,
+            Team::Enemy => -1,
+        };
+        let moves = (turns as i32 + 1) / 2;
+        match score {
+            Score::Win => EngineScore::Mate(sign * moves),
+            Score::Lose => EngineScore::Mate(-sign * moves),
+            /* Heuristic scores are already in centipawns. */
+            Score::Heuristic(h) => EngineScore::Centipawns(sign * h),
+        }
+    }
+}
+
+/// A depth the search has finished.
+#[derive(Clone,Debug)]
+pub struct Iteration {
+    pub depth: u32,
+    pub mv: ChessMove,
+    /// The principal variation, in the order the moves are played.
+    pub pv: Vec<ChessMove>,
+    pub score: EngineScore,
+    /// Nodes visited over every depth so far, quiescence search included.
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+/* Search events carry their search's `Control`, so that anything from a
+ * search that's been called off can be told apart and ignored. */
+#[derive(Debug)]
+pub enum Event {
+    Line(String),
+    EndOfInput,
+    Depth(Arc<Control>, Iteration),
+    Done(Arc<Control>),
+}
+
+/// Lines of input, plus whatever the search threads have to report.
+pub struct Input {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+impl Input {
+    fn new() -> Input {
+        let (sender, receiver) = mpsc::channel();
+        Input { sender, receiver }
+    }
+
+    /// Reads stdin on its own thread so commands can arrive mid-search.
+    pub fn from_stdin() -> Input {
+        let input = Input::new();
+        let sender = input.sender.clone();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(Event::Line(line)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            let _ = sender.send(Event::EndOfInput);
+        });
+        input
+    }
+
+    /// Waits for the next line, passing over anything left behind by
+    /// searches that were called off. None once input runs out.
+    pub fn next_line(&self) -> Option<String> {
+        loop {
+            match self.receiver.recv() {
+                Ok(Event::Line(line)) => return Some(line),
+                Ok(Event::EndOfInput) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// What to do about a line that arrives mid-search.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Interrupt {
+    /// Move now.
+    Stop,
+    /// Move now, then quit.
+    Quit,
+    /// Stop without moving, then handle it once the search is over.
+    Abandon,
+    /// Already dealt with.
+    Handled,
+    /// Handle it once the search is over.
+    Later,
+}
+
+/// The outcome of `Searcher::think`.
+#[derive(Debug)]
+pub struct Thought {
+    /// None if the search was abandoned or there's no legal move.
+    pub best: Option<ChessMove>,
+    /// Lines to handle now that the search is over.
+    pub pending: Vec<String>,
+    pub quit: bool,
+}
+
+pub struct Searcher {
+    minimax: Arc<Mutex<Minimax<SearchBoard>>>,
+    pawns: Arc<Mutex<PawnTable>>,
+}
+
+impl Searcher {
+    pub fn new() -> Searcher {
+        Searcher {
+            minimax: Arc::new(Mutex::new(new_minimax())),
+            pawns: Arc::new(Mutex::new(PawnTable::new())),
+        }
+    }
+
+    /// Searches `board` until a limit is hit or `interrupt` says to stop,
+    /// and answers with the move from the last depth that finished.
+    /// `report` hears about each depth as it finishes, and `interrupt`
+    /// about each line of input that arrives in the meantime.
+    pub fn think<R, I>(&self, board: &ChessBoard, limits: Limits, input: &Input,
+                       mut report: R, mut interrupt: I) -> Thought
+        where R: FnMut(&Iteration),
+              I: FnMut(&str) -> Interrupt
+    {
+        let start = Instant::now();
+        let control = Arc::new(Control::new(limits.nodes, self.pawns.clone()));
+        self.spawn(board.clone_for_search(), limits, control.clone(), start, input.sender.clone());
+
+        let deadline = limits.budget.map(|budget| start + budget.hard);
+        let mut thought = Thought { best: None, pending: Vec::new(), quit: false };
+        let mut abandoned = false;
+        loop {
+            let event = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    match input.receiver.recv_timeout(deadline - now) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => Event::EndOfInput,
+                    }
+                }
+                None => input.receiver.recv().unwrap_or(Event::EndOfInput),
+            };
+
+            match event {
+                Event::Line(line) => match interrupt(line.trim()) {
+                    Interrupt::Stop => break,
+                    Interrupt::Quit => {
+                        thought.quit = true;
+                        break;
+                    }
+                    Interrupt::Abandon => {
+                        thought.pending.push(line);
+                        abandoned = true;
+                        break;
+                    }
+                    Interrupt::Handled => {}
+                    Interrupt::Later => thought.pending.push(line),
+                },
+                Event::EndOfInput => {
+                    thought.quit = true;
+                    break;
+                }
+                Event::Depth(from, iteration) => {
+                    if Arc::ptr_eq(&from, &control) {
+                        thought.best = Some(iteration.mv);
+                        report(&iteration);
+                    }
+                }
+                Event::Done(from) => {
+                    /* In infinite mode the GUI expects us to wait for `stop`. */
+                    if Arc::ptr_eq(&from, &control) && !limits.infinite {
+                        break;
+                    }
+                }
+            }
+        }
+
+        /* Call the worker off, if it's still going. */
+        control.stop();
+        if abandoned {
+            thought.best = None;
+        } else if thought.best.is_none() {
+            /* Not even the first depth finished, but any legal move beats
+             * no move at all. */
+            thought.best = board.legal_moves().first().cloned();
+        }
+        thought
+    }
+
+    fn spawn(&self, board: ChessBoard, limits: Limits, control: Arc<Control>, start: Instant,
+             sender: Sender<Event>) {
+        let minimax = self.minimax.clone();
+        thread::spawn(move || {
+            /* Waits for any search that was called off to finish unwinding. */
+            let mut minimax = minimax.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let turn = board.turn();
+            let board = SearchBoard::with_control(board, control.clone());
+            for depth in 1..limits.max_depth + 1 {
+                let stats = minimax.minimax(&board, turn, depth as _);
+                if control.cancelled() {
+                    /* The table may hold scores from positions that were cut
+                     * short, so start over with an empty one. */
+                    *minimax = new_minimax();
+                    break;
+                }
+                let mv = match stats.mv {
+                    Some(mv) => mv,
+                    None => break,
+                };
+
+                let elapsed = start.elapsed();
+                let is_decided = match stats.score.score {
+                    Score::Win | Score::Lose => true,
+                    Score::Heuristic(_) => false,
+                };
+                let iteration = Iteration {
+                    depth,
+                    mv,
+                    pv: stats.mvs.iter().rev().cloned().collect(),
+                    score: EngineScore::new(stats.score.score, stats.score.turns as u32, turn),
+                    nodes: control.nodes(),
+                    elapsed,
+                };
+                if sender.send(Event::Depth(control.clone(), iteration)).is_err() {
+                    break;
+                }
+
+                /* The next depth takes several times as long as this one. */
+                let out_of_time = limits.budget.iter().any(|budget| elapsed * 2 > budget.soft);
+                if out_of_time || (is_decided && !limits.infinite) {
+                    break;
+                }
+            }
+            let _ = sender.send(Event::Done(control));
+        });
+    }
+}
+
+fn new_minimax() -> Minimax<SearchBoard> {
+    Minimax::new(NonZeroUsize::new(TABLE_SIZE).unwrap())
+}
+
+#[test]
+fn test_budget_from_clock() {
+    let budget = Budget::from_clock(Duration::from_millis(60000), Duration::from_millis(1000), 30);
+    assert_eq!(budget, Budget {
+        soft: Duration::from_millis(2500),
+        hard: Duration::from_millis(7500),
+    });
+
+    let budget = Budget::from_clock(Duration::from_millis(1000), Duration::from_millis(0), 1);
+    assert_eq!(budget, Budget::fixed(Duration::from_millis(500)));
+}
+
+#[test]
+fn test_limits() {
+    assert_eq!(Limits::new(Some(7), None, None, false).max_depth, 7);
+    assert_eq!(Limits::new(Some(100), None, None, false).max_depth, MAX_DEPTH);
+    assert_eq!(Limits::new(None, None, Some(5000), false).max_depth, MAX_DEPTH);
+    assert_eq!(Limits::new(None, None, None, true).max_depth, MAX_DEPTH);
+    assert_eq!(Limits::new(None, None, None, false).max_depth, DEFAULT_DEPTH);
+}
+
+#[test]
+fn test_engine_score() {
+    assert_eq!(EngineScore::new(Score::Heuristic(200), 6, Team::Ally), EngineScore::Centipawns(200));
+    assert_eq!(EngineScore::new(Score::Heuristic(200), 6, Team::Enemy), EngineScore::Centipawns(-200));
+    assert_eq!(EngineScore::new(Score::Win, 3, Team::Ally), EngineScore::Mate(2));
+    assert_eq!(EngineScore::new(Score::Win, 3, Team::Enemy), EngineScore::Mate(-2));
+    assert_eq!(EngineScore::new(Score::Lose, 4, Team::Enemy), EngineScore::Mate(2));
+}
+
+#[test]
+fn test_search_board_score() {
+    let board = ChessBoard::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3").unwrap();
+    let pawns = Arc::new(Mutex::new(PawnTable::new()));
+    let board = SearchBoard::new(board, pawns.clone());
+    assert!(matches!(board.score(), Score::Lose));
+    assert!(board.is_game_over());
+
+    let board = SearchBoard::new(ChessBoard::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap(),
+                                 pawns.clone());
+    assert!(matches!(board.score(), Score::Heuristic(0)));
+    assert!(board.is_game_over());
+
+    let board = SearchBoard::new(ChessBoard::new(), pawns);
+    assert!(!board.is_game_over());
+    assert_eq!(board.control.nodes(), 1);
+
+    /* Once called off, every position looks finished and even. */
+    board.control.stop();
+    assert!(board.is_game_over());
+    assert!(matches!(board.score(), Score::Heuristic(0)));
+}
+
+#[test]
+fn test_think_to_depth() {
+    let input = Input::new();
+    let mut depths = Vec::new();
+    let thought = Searcher::new().think(&ChessBoard::new(), Limits::new(Some(2), None, None, false), &input,
+                                        |iteration| depths.push(iteration.depth),
+                                        |_| Interrupt::Later);
+    assert_eq!(depths, [1, 2]);
+    assert!(thought.best.is_some());
+    assert!(!thought.quit);
+}
+
+#[test]
+fn test_think_stops_mid_depth() {
+    let input = Input::new();
+    let sender = input.sender.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        sender.send(Event::Line("ping".to_string())).unwrap();
+        sender.send(Event::Line("stop".to_string())).unwrap();
+    });
+
+    let start = Instant::now();
+    let board = ChessBoard::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let limits = Limits::new(None, None, None, true);
+    let thought = Searcher::new().think(&board, limits, &input, |_| {}, |line| match line {
+        "stop" => Interrupt::Stop,
+        _ => Interrupt::Later,
+    });
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(board.legal_moves().contains(&thought.best.unwrap()));
+    assert_eq!(thought.pending, ["ping"]);
+}
+
+#[test]
+fn test_think_abandoned() {
+    let input = Input::new();
+    let sender = input.sender.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        sender.send(Event::Line("force".to_string())).unwrap();
+    });
+
+    let board = ChessBoard::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+    let limits = Limits::new(None, None, None, true);
+    let thought = Searcher::new().think(&board, limits, &input, |_| {}, |line| match line {
+        "force" => Interrupt::Abandon,
+        _ => Interrupt::Later,
+    });
+    assert!(thought.best.is_none());
+    assert_eq!(thought.pending, ["force"]);
+    assert!(!thought.quit);
+}
+
+#[test]
+fn test_think_hard_limits() {
+    let input = Input::new();
+    let board = ChessBoard::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+
+    let start = Instant::now();
+    let timed = Limits::new(None, Some(Budget::fixed(Duration::from_millis(100))), None, false);
+    let thought = Searcher::new().think(&board, timed, &input, |_| {}, |_| Interrupt::Later);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(board.legal_moves().contains(&thought.best.unwrap()));
+
+    let mut nodes = Vec::new();
+    let counted = Limits::new(None, None, Some(5000), false);
+    let thought = Searcher::new().think(&board, counted, &input,
+                                        |iteration| nodes.push(iteration.nodes),
+                                        |_| Interrupt::Later);
+    assert!(!nodes.is_empty());
+    assert!(nodes.iter().all(|&n| n < 5000));
+    assert!(board.legal_moves().contains(&thought.best.unwrap()));
+}
@@ -0,0 +1,245 @@
+use chess_board::{ChessBoard, ChessMove};
+use search::{Budget, EngineScore, Input, Interrupt, Iteration, Limits, Searcher};
+
+use minimax::Team;
+
+use std::time::Duration;
+
+/// Limits parsed from a `go` command.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+struct GoParams {
+    depth: Option<u32>,
+    nodes: Option<u64>,
+    movetime: Option<u64>,
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movestogo: Option<u64>,
+    infinite: bool,
+}
+
+impl GoParams {
+    fn parse(args: &[&str]) -> GoParams {
+        let mut params = GoParams::default();
+        let mut iter = args.iter();
+        while let Some(&arg) = iter.next() {
+            let mut value = || iter.next().and_then(|v| v.parse().ok());
+            match arg {
+                "depth" => params.depth = value().map(|d: u64| d as u32),
+                "nodes" => params.nodes = value(),
+                "movetime" => params.movetime = value(),
+                "wtime" => params.wtime = value(),
+                "btime" => params.btime = value(),
+                "winc" => params.winc = value(),
+                "binc" => params.binc = value(),
+                "movestogo" => params.movestogo = value(),
+                "infinite" => params.infinite = true,
+                _ => {}
+            }
+        }
+        params
+    }
+
+    /// Works out how long to think for, if there's a clock to respect.
+    fn budget(&self, turn: Team) -> Option<Budget> {
+        if self.infinite {
+            return None;
+        }
+        if let Some(movetime) = self.movetime {
+            return Some(Budget::fixed(Duration::from_millis(movetime)));
+        }
+
+        let (time, inc) = match turn {
+            Team::Ally => (self.wtime, self.winc),
+            Team::Enemy => (self.btime, self.binc),
+        };
+        time.map(|time| Budget::from_clock(Duration::from_millis(time),
+                                           Duration::from_millis(inc.unwrap_or(0)),
+                                           self.movestogo.unwrap_or(30) as u32))
+    }
+
+    fn limits(&self, turn: Team) -> Limits {
+        Limits::new(self.depth, self.budget(turn), self.nodes, self.infinite)
+    }
+}
+
+/// Runs the engine over the Universal Chess Interface on stdin/stdout.
+pub fn run() {
+    let input = Input::from_stdin();
+    let mut uci = Uci::new();
+    while let Some(line) = input.next_line() {
+        if !uci.handle(&line, &input) {
+            break;
+        }
+    }
+}
+
+struct Uci {
+    /// None after a `position` that couldn't be set up.
+    board: Option<ChessBoard>,
+    searcher: Searcher,
+}
+
+impl Uci {
+    fn new() -> Uci {
+        Uci {
+            board: Some(ChessBoard::new()),
+            searcher: Searcher::new(),
+        }
+    }
+
+    /// Handles one command, returning false once it's time to quit.
+    fn handle(&mut self, line: &str, input: &Input) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"uci") => {
+                println!("id name rusty_chess_ai");
+                println!("id author Isaac Lozano");
+                println!("uciok");
+            }
+            Some(&"isready") => println!("readyok"),
+            Some(&"ucinewgame") => *self = Uci::new(),
+            Some(&"position") => match parse_position(&words[1..]) {
+                Ok(board) => self.board = Some(board),
+                Err(err) => {
+                    /* Searching the last good position would only answer
+                     * with a move that makes no sense in this one. */
+                    println!("info string {}", err);
+                    self.board = None;
+                }
+            },
+            Some(&"go") => return self.go(GoParams::parse(&words[1..]), input),
+            Some(&"quit") => return false,
+            _ => {}
+        }
+        true
+    }
+
+    /// Searches until a limit is hit or `stop` arrives, then answers with
+    /// the move from the last depth that finished.
+    fn go(&mut self, params: GoParams, input: &Input) -> bool {
+        let board = match self.board {
+            Some(ref board) => board,
+            None => {
+                println!("info string no position to search");
+                println!("bestmove 0000");
+                return true;
+            }
+        };
+        let turn = board.turn();
+        let report = |iteration: &Iteration| {
+            let millis = iteration.elapsed.as_secs() * 1000
+                + iteration.elapsed.subsec_nanos() as u64 / 1000000;
+            let pv: Vec<String> = iteration.pv.iter().map(uci_move).collect();
+            println!("info depth {} score {} nodes {} nps {} time {} pv {}",
+                     iteration.depth,
+                     uci_score(iteration.score),
+                     iteration.nodes,
+                     iteration.nodes * 1000 / millis.max(1),
+                     millis,
+                     pv.join(" "));
+        };
+        let interrupt = |line: &str| match line {
+            "stop" => Interrupt::Stop,
+            "quit" => Interrupt::Quit,
+            "isready" => {
+                println!("readyok");
+                Interrupt::Handled
+            }
+            _ => Interrupt::Later,
+        };
+        let thought = self.searcher.think(board, params.limits(turn), input, report, interrupt);
+
+        match thought.best {
+            Some(mv) => println!("bestmove {}", uci_move(&mv)),
+            None => println!("bestmove 0000"),
+        }
+
+        if thought.quit {
+            return false;
+        }
+        for line in thought.pending {
+            if !self.handle(&line, input) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parses the arguments of a `position` command: `startpos` or
+/// `fen <fen>`, then optionally `moves` and a list of moves.
+fn parse_position(args: &[&str]) -> Result<ChessBoard, String> {
+    let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+    let mut board = match args.first() {
+        Some(&"startpos") => ChessBoard::new(),
+        Some(&"fen") => {
+            let fen = args[1..moves_at].join(" ");
+            ChessBoard::from_fen(&fen).map_err(|err| format!("bad fen \"{}\": {}", fen, err))?
+        }
+        Some(arg) => return Err(format!("unknown position \"{}\"", arg)),
+        None => return Err("missing position".to_string()),
+    };
+
+    for s in args.iter().skip(moves_at + 1) {
+        let mv = board.move_from_str(s).ok()
+            .filter(|mv| board.legal_moves().contains(mv))
+            .ok_or_else(|| format!("illegal move {} in {}", s, board.to_fen()))?;
+        board.do_move(&mv);
+    }
+    Ok(board)
+}
+
+/// Formats a move in UCI's long algebraic notation, such as "e7e8q".
+fn uci_move(mv: &ChessMove) -> String {
+    mv.to_string().to_lowercase()
+}
+
+fn uci_score(score: EngineScore) -> String {
+    match score {
+        EngineScore::Mate(moves) => format!("mate {}", moves),
+        EngineScore::Centipawns(cp) => format!("cp {}", cp),
+    }
+}
+
+#[test]
+fn test_go_params() {
+    let params = GoParams::parse(&["wtime", "60000", "btime", "30000", "winc", "1000",
+                                   "binc", "500", "depth", "7"]);
+    assert_eq!(params.depth, Some(7));
+    assert_eq!(params.budget(Team::Ally).unwrap().soft, Duration::from_millis(2500));
+    assert_eq!(params.budget(Team::Enemy).unwrap().soft, Duration::from_millis(1250));
+
+    let params = GoParams::parse(&["movetime", "100", "infinite"]);
+    assert!(params.infinite);
+    assert_eq!(params.budget(Team::Ally), None);
+    assert_eq!(GoParams::parse(&["movetime", "100"]).budget(Team::Enemy),
+               Some(Budget::fixed(Duration::from_millis(100))));
+    assert_eq!(GoParams::parse(&["nodes", "5000"]).nodes, Some(5000));
+}
+
+#[test]
+fn test_parse_position() {
+    let board = parse_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"]).unwrap();
+    assert_eq!(board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+
+    let fen = ["fen", "4k3/4P3/8/8/8/8/8/4K3", "w", "-", "-", "0", "1", "moves", "e1d2"];
+    assert_eq!(parse_position(&fen).unwrap().to_fen(), "4k3/4P3/8/8/8/8/3K4/8 b - - 1 1");
+
+    let promote = ["fen", "8/4P1k1/8/8/8/8/8/4K3", "w", "-", "-", "0", "1", "moves", "e7e8n"];
+    assert_eq!(parse_position(&promote).unwrap().to_fen(), "4N3/6k1/8/8/8/8/8/4K3 b - - 0 1");
+
+    assert_eq!(parse_position(&["startpos", "moves", "e2e4", "e2e5"]).unwrap_err(),
+               "illegal move e2e5 in rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    assert!(parse_position(&["fen", "nonsense"]).unwrap_err().starts_with("bad fen \"nonsense\""));
+    assert!(parse_position(&["startfen"]).is_err());
+    assert!(parse_position(&[]).is_err());
+}
+
+#[test]
+fn test_uci_score() {
+    assert_eq!(uci_score(EngineScore::Centipawns(-200)), "cp -200");
+    assert_eq!(uci_score(EngineScore::Mate(2)), "mate 2");
+    assert_eq!(uci_score(EngineScore::Mate(-2)), "mate -2");
+}
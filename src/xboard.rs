@@ -0,0 +1,323 @@
+use chess_board::{ChessBoard, ChessMove, DrawReason, GameOutcome};
+use search::{Budget, EngineScore, Input, Interrupt, Iteration, Limits, Searcher, MAX_DEPTH};
+
+use minimax::Team;
+
+use std::time::Duration;
+
+/// The time control set by `level`.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+struct Level {
+    /// Moves per time control, or zero for the whole game.
+    moves: u32,
+    increment: Duration,
+}
+
+impl Level {
+    /// Parses the arguments of `level <moves> <base> <increment>`,
+    /// where the base is either minutes or "minutes:seconds". Only the
+    /// move count and increment matter to us: `time` tells us the rest.
+    fn parse(args: &[&str]) -> Option<Level> {
+        if args.len() != 3 {
+            return None;
+        }
+        let moves = args[0].parse().ok()?;
+        let mut base = args[1].split(':');
+        base.next()?.parse::<u64>().ok()?;
+        if let Some(seconds) = base.next() {
+            seconds.parse::<u64>().ok()?;
+        }
+        let increment = args[2].parse::<f64>().ok().filter(|&inc| inc >= 0.0)?;
+        Some(Level {
+            moves,
+            increment: Duration::from_millis((increment * 1000.0) as u64),
+        })
+    }
+
+    /// Works out how long to think with `left` on the clock, given how
+    /// many moves the engine has already made this game.
+    fn budget(&self, left: Duration, moves_made: u32) -> Budget {
+        let moves_to_go = match self.moves {
+            0 => 30,
+            moves => moves - moves_made % moves,
+        };
+        Budget::from_clock(left, self.increment, moves_to_go)
+    }
+}
+
+/// Runs the engine over the Chess Engine Communication Protocol used by
+/// XBoard and WinBoard on stdin/stdout.
+pub fn run() {
+    let input = Input::from_stdin();
+    let mut xboard = XBoard::new();
+    while let Some(line) = input.next_line() {
+        if !xboard.handle(&line, &input) {
+            break;
+        }
+    }
+}
+
+struct XBoard {
+    /// Where the game started, so `undo` can replay up to a move.
+    start: ChessBoard,
+    moves: Vec<ChessMove>,
+    board: ChessBoard,
+    /// The team the engine plays, or `None` in force mode.
+    engine: Option<Team>,
+    searcher: Searcher,
+    level: Level,
+    /// Fixed time per move from `st`.
+    move_time: Option<Duration>,
+    /// Depth limit from `sd`.
+    max_depth: Option<u32>,
+    /// The engine's clock, as last reported by `time`.
+    clock: Option<Duration>,
+    /// Whether to print thinking output.
+    post: bool,
+}
+
+impl XBoard {
+    fn new() -> XBoard {
+        XBoard {
+            start: ChessBoard::new(),
+            moves: Vec::new(),
+            board: ChessBoard::new(),
+            engine: Some(Team::Enemy),
+            searcher: Searcher::new(),
+            level: Level::default(),
+            move_time: None,
+            max_depth: None,
+            clock: None,
+            post: false,
+        }
+    }
+
+    fn engine_to_move(&self) -> bool {
+        matches!((self.engine, self.board.turn()),
+                 (Some(Team::Ally), Team::Ally) | (Some(Team::Enemy), Team::Enemy))
+    }
+
+    /// Handles one command, returning false once it's time to quit.
+    fn handle(&mut self, line: &str, input: &Input) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let args = if words.is_empty() { &words[..] } else { &words[1..] };
+        match words.first() {
+            None | Some(&"xboard") | Some(&"accepted") | Some(&"rejected") |
+            Some(&"random") | Some(&"hard") | Some(&"easy") | Some(&"computer") |
+            Some(&"otim") | Some(&"?") => {}
+            Some(&"protover") => {
+                println!("feature myname=\"rusty_chess_ai\" ping=1 setboard=1 usermove=1 \
+                          san=0 colors=0 sigint=0 sigterm=0 done=1");
+            }
+            Some(&"ping") => println!("pong {}", args.join(" ")),
+            Some(&"new") => *self = XBoard { post: self.post, ..XBoard::new() },
+            Some(&"force") | Some(&"result") => self.engine = None,
+            Some(&"go") => {
+                self.engine = Some(self.board.turn());
+                return self.think(input);
+            }
+            Some(&"playother") => self.engine = Some(self.board.turn().other_team()),
+            Some(&"usermove") => return self.user_move(args.first().cloned().unwrap_or(""), input),
+            Some(&"setboard") => match ChessBoard::from_fen(&args.join(" ")) {
+                Ok(board) => {
+                    self.start = board.clone();
+                    self.board = board;
+                    self.moves.clear();
+                }
+                Err(e) => println!("tellusererror Illegal position: {}", e),
+            },
+            Some(&"level") => match Level::parse(args) {
+                Some(level) => {
+                    self.level = level;
+                    self.move_time = None;
+                }
+                None => println!("Error (bad arguments): {}", line),
+            },
+            Some(&"st") => match args.first().and_then(|s| s.parse().ok()) {
+                Some(seconds) => self.move_time = Some(Duration::from_secs(seconds)),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            Some(&"sd") => match args.first().and_then(|s| s.parse::<u32>().ok()) {
+                Some(depth) => self.max_depth = Some(depth.clamp(1, MAX_DEPTH)),
+                None => println!("Error (bad arguments): {}", line),
+            },
+            Some(&"time") => {
+                /* Clocks are given in centiseconds. */
+                self.clock = args.first()
+                    .and_then(|s| s.parse().ok())
+                    .map(|cs: u64| Duration::from_millis(cs * 10));
+            }
+            Some(&"undo") => self.take_back(1),
+            Some(&"remove") => self.take_back(2),
+            Some(&"post") => self.post = true,
+            Some(&"nopost") => self.post = false,
+            Some(&"quit") => return false,
+            Some(_) => println!("Error (unknown command): {}", words[0]),
+        }
+        true
+    }
+
+    fn user_move(&mut self, s: &str, input: &Input) -> bool {
+        let mv = self.board.move_from_str(s)
+            .ok()
+            .filter(|mv| self.board.legal_moves().contains(mv))
+            .or_else(|| self.board.move_from_san(s).ok());
+        match mv {
+            Some(mv) => self.play(mv),
+            None => {
+                println!("Illegal move: {}", s);
+                return true;
+            }
+        }
+        if self.engine_to_move() && self.is_ongoing() {
+            return self.think(input);
+        }
+        true
+    }
+
+    fn play(&mut self, mv: ChessMove) {
+        self.board.do_move(&mv);
+        self.moves.push(mv);
+        self.report_result();
+    }
+
+    /// Takes back the last `plies` moves by replaying the game from the
+    /// start without them.
+    fn take_back(&mut self, plies: usize) {
+        let keep = self.moves.len().saturating_sub(plies);
+        self.moves.truncate(keep);
+        self.board = self.start.clone();
+        for mv in self.moves.iter() {
+            self.board.do_move(mv);
+        }
+    }
+
+    fn is_ongoing(&self) -> bool {
+        matches!(self.board.outcome(), GameOutcome::Ongoing)
+    }
+
+    /// Tells the GUI when the game is over, since it may not be tracking
+    /// the draw rules itself.
+    fn report_result(&self) {
+        match self.board.outcome() {
+            GameOutcome::Ongoing => {}
+            GameOutcome::Checkmate(Team::Ally) => println!("1-0 {{White mates}}"),
+            GameOutcome::Checkmate(Team::Enemy) => println!("0-1 {{Black mates}}"),
+            GameOutcome::Stalemate => println!("1/2-1/2 {{Stalemate}}"),
+            GameOutcome::Draw(reason) => println!("1/2-1/2 {{{}}}", draw_comment(reason)),
+        }
+    }
+
+    fn budget(&self) -> Option<Budget> {
+        if let Some(move_time) = self.move_time {
+            return Some(Budget::fixed(move_time));
+        }
+        let moves_made = (self.moves.len() / 2) as u32;
+        self.clock.map(|left| self.level.budget(left, moves_made))
+    }
+
+    /// Searches for and plays the engine's move, stopping early if `?`
+    /// asks us to move now. If the GUI takes the game away from us with
+    /// `force`, `result` or `new`, the move is dropped instead.
+    fn think(&mut self, input: &Input) -> bool {
+        let limits = Limits::new(self.max_depth, self.budget(), None, false);
+        let post = self.post;
+        let board = &self.board;
+        let report = |iteration: &Iteration| {
+            if !post {
+                return;
+            }
+            let mut line = board.clone();
+            let pv: Vec<String> = iteration.pv.iter()
+                .map(|mv| {
+                    let san = line.move_to_san(mv);
+                    line.do_move(mv);
+                    san
+                })
+                .collect();
+            println!("{} {} {} {} {}",
+                     iteration.depth,
+                     xboard_score(iteration.score),
+                     iteration.elapsed.as_secs() * 100 + iteration.elapsed.subsec_nanos() as u64 / 10000000,
+                     iteration.nodes,
+                     pv.join(" "));
+        };
+        let interrupt = |line: &str| match line.split_whitespace().next() {
+            Some("?") => Interrupt::Stop,
+            Some("quit") => Interrupt::Quit,
+            Some("force") | Some("result") | Some("new") => Interrupt::Abandon,
+            _ => Interrupt::Later,
+        };
+        let thought = self.searcher.think(board, limits, input, report, interrupt);
+
+        if thought.quit {
+            return false;
+        }
+        if let Some(mv) = thought.best {
+            println!("move {}", mv.to_string().to_lowercase());
+            self.play(mv);
+        }
+        for line in thought.pending {
+            if !self.handle(&line, input) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn draw_comment(reason: DrawReason) -> &'static str {
+    match reason {
+        DrawReason::FiftyMoveRule => "Draw by fifty move rule",
+        DrawReason::ThreefoldRepetition => "Draw by repetition",
+        DrawReason::InsufficientMaterial => "Insufficient material",
+    }
+}
+
+/// Formats a search score in centipawns, using the protocol's
+/// 100000 + moves convention for mates.
+fn xboard_score(score: EngineScore) -> i32 {
+    match score {
+        EngineScore::Mate(moves) => moves.signum() * (100000 + moves.abs()),
+        EngineScore::Centipawns(cp) => cp,
+    }
+}
+
+#[test]
+fn test_level() {
+    let level = Level::parse(&["40", "5", "0"]).unwrap();
+    assert_eq!(level.moves, 40);
+    assert_eq!(level.budget(Duration::from_secs(300), 0).soft, Duration::from_millis(7500));
+    assert_eq!(level.budget(Duration::from_secs(30), 38), Budget::fixed(Duration::from_secs(15)));
+
+    let level = Level::parse(&["0", "2:30", "1.5"]).unwrap();
+    assert_eq!(level.increment, Duration::from_millis(1500));
+    assert_eq!(level.budget(Duration::from_secs(60), 10).soft, Duration::from_millis(2750));
+
+    assert!(Level::parse(&["40", "five", "0"]).is_none());
+    assert!(Level::parse(&["40", "5"]).is_none());
+}
+
+#[test]
+fn test_take_back() {
+    let mut xboard = XBoard::new();
+    xboard.engine = None;
+    for s in ["e2e4", "e7e5", "g1f3"].iter() {
+        let mv = xboard.board.move_from_str(s).unwrap();
+        xboard.play(mv);
+    }
+    xboard.take_back(2);
+    assert_eq!(xboard.board.to_fen(),
+               "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    xboard.take_back(1);
+    assert_eq!(xboard.board.to_fen(), ChessBoard::new().to_fen());
+    xboard.take_back(1);
+    assert!(xboard.moves.is_empty());
+}
+
+#[test]
+fn test_xboard_score() {
+    assert_eq!(xboard_score(EngineScore::Centipawns(-200)), -200);
+    assert_eq!(xboard_score(EngineScore::Mate(2)), 100002);
+    assert_eq!(xboard_score(EngineScore::Mate(-2)), -100002);
+}
@@ -0,0 +1,113 @@
+use chess_board::Piece;
+
+use bitboard::BitboardPiece;
+
+/* Random numbers for Zobrist hashing. A position's key is the xor of
+ * the numbers for everything in it, so a move only has to xor in and
+ * out the few things it changes. They're generated at compile time from
+ * a fixed seed so keys stay the same from run to run.
+ */
+struct Keys {
+    /* Indexed by colour (White first), piece and square. */
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+    /* White kingside, White queenside, Black kingside, Black queenside. */
+    castling: [u64; 4],
+    /* Indexed by the file of the en passant target. */
+    en_passant: [u64; 8],
+}
+
+const fn xorshift(mut state: u64) -> u64 {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    state
+}
+
+const fn generate() -> Keys {
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        black_to_move: 0,
+        castling: [0; 4],
+        en_passant: [0; 8],
+    };
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+
+    let mut team = 0;
+    while team < 2 {
+        let mut piece = 0;
+        while piece < 6 {
+            let mut square = 0;
+            while square < 64 {
+                state = xorshift(state);
+                keys.pieces[team][piece][square] = state;
+                square += 1;
+            }
+            piece += 1;
+        }
+        team += 1;
+    }
+
+    state = xorshift(state);
+    keys.black_to_move = state;
+
+    let mut i = 0;
+    while i < 4 {
+        state = xorshift(state);
+        keys.castling[i] = state;
+        i += 1;
+    }
+
+    let mut file = 0;
+    while file < 8 {
+        state = xorshift(state);
+        keys.en_passant[file] = state;
+        file += 1;
+    }
+
+    keys
+}
+
+static KEYS: Keys = generate();
+
+/// The key for a white or black `piece` standing on `square`.
+pub fn piece(white: bool, piece: Piece, square: BitboardPiece) -> u64 {
+    let colour = if white { 0 } else { 1 };
+    KEYS.pieces[colour][piece as usize][square.rank() * 8 + square.file()]
+}
+
+/// Xored in whenever it's Black's turn.
+pub fn black_to_move() -> u64 {
+    KEYS.black_to_move
+}
+
+/// The key for both teams' castling rights together, given in the
+/// order White kingside, White queenside, Black kingside, Black queenside.
+pub fn castling(rights: [bool; 4]) -> u64 {
+    rights.iter()
+        .zip(KEYS.castling.iter())
+        .filter(|&(&right, _)| right)
+        .fold(0, |key, (_, &k)| key ^ k)
+}
+
+/// The key for an en passant target on `square`.
+pub fn en_passant(square: BitboardPiece) -> u64 {
+    KEYS.en_passant[square.file()]
+}
+
+#[test]
+fn test_keys_are_distinct() {
+    let mut all: Vec<u64> = KEYS.pieces.iter()
+        .flat_map(|team| team.iter())
+        .flat_map(|piece| piece.iter())
+        .chain(KEYS.castling.iter())
+        .chain(KEYS.en_passant.iter())
+        .cloned()
+        .collect();
+    all.push(KEYS.black_to_move);
+    let count = all.len();
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), count);
+    assert!(all.iter().all(|&key| key != 0));
+}